@@ -0,0 +1,68 @@
+//! Finding files that worlds refer to by name.
+//!
+//! Most worlds were made on DOS or Windows, so they name files with backslashes
+//! and without regard to case. Each component of such a name is matched against
+//! the real directory contents, ignoring case when there is no exact match.
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub(crate) struct AssetResolver {
+    root: PathBuf,
+    /// The entries of every directory looked at so far.
+    listings: HashMap<PathBuf, Vec<OsString>>,
+}
+
+impl AssetResolver {
+    pub fn new(root: &Path) -> AssetResolver {
+        AssetResolver {
+            root: root.to_owned(),
+            listings: HashMap::new(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn listing(&mut self, dir: &Path) -> &[OsString] {
+        self.listings
+            .entry(dir.to_owned())
+            .or_insert_with(|| match fs::read_dir(dir) {
+                Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.file_name()).collect(),
+                Err(_) => vec![],
+            })
+    }
+
+    /// The path of the file a world calls `name`. Components that can't be
+    /// found are kept as written, so the result can still be used in errors.
+    pub fn resolve(&mut self, name: &str) -> PathBuf {
+        let mut path = self.root.clone();
+        for component in name.split(|c| c == '/' || c == '\\') {
+            if component.is_empty() || component == "." {
+                continue;
+            }
+            if component == ".." {
+                path.push(component);
+                continue;
+            }
+            let found = {
+                let entries = self.listing(&path);
+                entries
+                    .iter()
+                    .find(|entry| entry.to_str() == Some(component))
+                    .or_else(|| entries.iter().find(|entry| {
+                        entry.to_string_lossy().eq_ignore_ascii_case(component)
+                    }))
+                    .cloned()
+            };
+            match found {
+                Some(entry) => path.push(entry),
+                None => path.push(component),
+            }
+        }
+        path
+    }
+}
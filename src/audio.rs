@@ -1,15 +1,40 @@
+//! Music, sound effects and samples.
+//!
+//! The game talks to the audio system through a `MusicCallback`, which only
+//! sends commands down a channel. A `Mixer` on a thread of its own owns
+//! everything that is playing: it handles those commands and renders a little
+//! way ahead of the audio device into a `SampleRing`. SDL's audio callback only
+//! copies samples out of that ring, so it never blocks, allocates or frees
+//! anything. Each module is read, decoded and rendered on a thread of its own,
+//! which hands the mixer samples through another ring, so modules never leave
+//! the thread that created them and decoding a large one can't hold up the
+//! mixer.
+
+use crate::assets::AssetResolver;
+use crate::ring::SampleRing;
+use crate::samples::{Sample, SampleCache, SamplePlayer};
+#[cfg(feature = "robot-audio")]
+use crate::sfx::ROBOT_PRIORITY;
+use crate::sfx::{Speaker, Tone, parse_play};
 use libmzx::audio::AudioEngine;
 use openmpt::module::{Module, Logger};
 use sdl2::AudioSubsystem;
 use sdl2::audio::{AudioDevice, AudioSpecDesired, AudioCallback};
+use std::cell::RefCell;
 use std::fs::File;
 use std::io::Read;
+use std::mem;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicU8, Ordering};
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
 pub fn init_sdl(
     audio_subsystem: &AudioSubsystem,
-    music: MusicCallback
+    mixer: Mixer,
 ) -> AudioDevice<impl AudioCallback> {
     let desired_spec = AudioSpecDesired {
         freq: Some(44100),
@@ -17,118 +42,645 @@ pub fn init_sdl(
         samples: None,
     };
     let device = audio_subsystem.open_playback(None, &desired_spec, move |spec| {
-        music.0.lock().unwrap().rate = spec.freq;
-        music
+        // The ring has to hold at least what the device asks for at once.
+        let frames = RENDER_AHEAD_FRAMES.max(2 * spec.samples as usize);
+        mixer.start(spec.freq, frames * spec.channels as usize)
     }).unwrap();
     device.resume();
     device
 }
 
-pub struct MusicData {
-    world_path: PathBuf,
+/// MegaZeux fades music over this many game cycles by default, so fades take
+/// longer at slower game speeds.
+pub const DEFAULT_FADE_CYCLES: u32 = 32;
+
+/// MegaZeux plays a SAM at this clock divided by twice its period.
+#[cfg(feature = "robot-audio")]
+const SAM_CLOCK_HZ: u32 = 3_579_364;
+
+/// How long the previous board's module keeps playing under the new one.
+const CROSSFADE_MS: u64 = 750;
+
+/// How far ahead of the audio device the mixer renders. Commands take up to this
+/// long to be heard.
+const RENDER_AHEAD_FRAMES: usize = 2048;
+/// How far ahead of the mixer each module's thread renders. Jumping to another
+/// order takes up to this long to be heard.
+const MODULE_AHEAD_FRAMES: usize = 2048;
+/// How many frames the mixer and module threads render at once.
+const RENDER_CHUNK_FRAMES: usize = 256;
+/// How long the mixer and module threads wait once they are far enough ahead.
+const RENDER_WAIT: Duration = Duration::from_millis(2);
+
+/// The states of a `ModuleStream`.
+const STREAM_LOADING: u8 = 0;
+const STREAM_READY: u8 = 1;
+const STREAM_FAILED: u8 = 2;
+
+enum Command {
+    WantModule { name: String, path: PathBuf, fade_in: bool },
+    /// The module asked for is already playing.
+    KeepModule,
+    EndModule,
+    FadeOut,
+    SetOrder(i32),
+    SetFadeLength { fade_cycles: u32, cycle_ms: u64 },
+    SetVolumes((f32, f32, f32)),
+    PlaySfx(Vec<Tone>, u8),
+    StopSfx,
+    PlaySample(Arc<Sample>, u32),
+}
+
+/// What the mixer and a module's thread share.
+struct StreamShared {
+    ring: SampleRing,
+    status: AtomicU8,
+    /// An order to jump to, or -1.
+    seek: AtomicIsize,
+    order: AtomicIsize,
+    stop: AtomicBool,
+}
+
+/// A module that is read, decoded and rendered on a thread of its own, since
+/// libopenmpt modules can't be sent between threads. Dropping it stops the
+/// thread.
+struct ModuleStream(Arc<StreamShared>);
+
+impl ModuleStream {
+    fn start(path: PathBuf, name: String, rate: i32) -> ModuleStream {
+        let shared = Arc::new(StreamShared {
+            ring: SampleRing::new(MODULE_AHEAD_FRAMES * 2),
+            status: AtomicU8::new(STREAM_LOADING),
+            seek: AtomicIsize::new(-1),
+            order: AtomicIsize::new(0),
+            stop: AtomicBool::new(false),
+        });
+        let thread_shared = shared.clone();
+        thread::spawn(move || render_module(&path, &name, rate, &thread_shared));
+        ModuleStream(shared)
+    }
+
+    fn status(&self) -> u8 {
+        self.0.status.load(Ordering::Acquire)
+    }
+
+    fn seek(&self, order: i32) {
+        self.0.seek.store(order as isize, Ordering::Relaxed);
+    }
+
+    fn order(&self) -> isize {
+        self.0.order.load(Ordering::Relaxed)
+    }
+
+    /// Fill `out`, with silence for anything the module's thread hasn't rendered
+    /// yet.
+    fn read(&self, out: &mut [f32]) {
+        let read = self.0.ring.pop(out);
+        for sample in &mut out[read..] {
+            *sample = 0.;
+        }
+    }
+}
+
+impl Drop for ModuleStream {
+    fn drop(&mut self) {
+        self.0.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The body of a module's thread. The module only counts as ready once its ring
+/// is full, so the mixer never switches to it before it can keep up.
+fn render_module(path: &Path, name: &str, rate: i32, shared: &StreamShared) {
+    let mut module = match read_module(path, name).and_then(|data| decode_module(&data, name)) {
+        Some(module) => module,
+        None => {
+            shared.status.store(STREAM_FAILED, Ordering::Release);
+            return;
+        }
+    };
+    let mut chunk = vec![0.; RENDER_CHUNK_FRAMES * 2];
+    while !shared.stop.load(Ordering::Relaxed) {
+        let seek = shared.seek.swap(-1, Ordering::Relaxed);
+        if seek >= 0 {
+            module.set_position_order_row(seek as i32, 0);
+        }
+        if shared.ring.free() < chunk.len() {
+            shared.status.store(STREAM_READY, Ordering::Release);
+            thread::sleep(RENDER_WAIT);
+            continue;
+        }
+        module.read_interleaved_float_stereo(rate, &mut chunk);
+        shared.order.store(module.get_current_order() as isize, Ordering::Relaxed);
+        shared.ring.push(&chunk);
+    }
+}
+
+/// A module that is being faded out while the current one fades in.
+struct Crossfade {
+    module: ModuleStream,
+    /// The outgoing module's volume when the crossfade started.
+    volume: f32,
+    remaining: u64,
+    total: u64,
+}
+
+/// A module that is still loading.
+struct PendingLoad {
+    name: String,
+    fade_in: bool,
+    stream: ModuleStream,
+}
+
+struct MusicData {
     rate: i32,
-    current_module: Option<(String, Module)>,
-    new_position: Option<i32>,
-    silent: bool,
+    current_module: Option<(String, ModuleStream)>,
+    crossfade: Option<Crossfade>,
+    pending_load: Option<PendingLoad>,
+    scratch: Vec<f32>,
+    speaker: Speaker,
+    samples: SamplePlayer,
+    /// Output levels for (music, samples, speaker), from 0 to 1.
+    volumes: (f32, f32, f32),
+    volume: f32,
+    fade_target: f32,
+    fade_cycles: u32,
+    cycle_ms: u64,
 }
 
 impl MusicData {
-    fn new(world_path: &Path, silent: bool) -> MusicData {
+    fn new(rate: i32) -> MusicData {
         MusicData {
-            world_path: world_path.to_owned(),
-            rate: 0,
+            rate,
             current_module: None,
-            new_position: None,
-            silent,
+            crossfade: None,
+            pending_load: None,
+            scratch: vec![],
+            speaker: Speaker::default(),
+            samples: SamplePlayer::default(),
+            volumes: (1., 1., 1.),
+            volume: 1.,
+            fade_target: 1.,
+            fade_cycles: DEFAULT_FADE_CYCLES,
+            cycle_ms: 48,
+        }
+    }
+
+    fn handle(&mut self, command: Command) {
+        match command {
+            Command::WantModule { name, path, fade_in } => {
+                if self.current_module.as_ref().map_or(false, |(current, _)| current == &name) {
+                    self.pending_load = None;
+                    self.keep_module();
+                } else {
+                    let stream = ModuleStream::start(path, name.clone(), self.rate);
+                    self.pending_load = Some(PendingLoad { name, fade_in, stream });
+                }
+            }
+            Command::KeepModule => self.keep_module(),
+            Command::EndModule => {
+                self.current_module = None;
+                self.crossfade = None;
+                self.pending_load = None;
+            }
+            Command::FadeOut => self.fade_target = 0.,
+            // An order change asked for along with a new module goes to that module.
+            Command::SetOrder(order) => match (&self.pending_load, &self.current_module) {
+                (Some(pending), _) => pending.stream.seek(order),
+                (None, Some((_, stream))) => stream.seek(order),
+                (None, None) => (),
+            },
+            Command::SetFadeLength { fade_cycles, cycle_ms } => {
+                self.fade_cycles = fade_cycles;
+                self.cycle_ms = cycle_ms;
+            }
+            Command::SetVolumes(volumes) => self.volumes = volumes,
+            Command::PlaySfx(tones, priority) => self.speaker.play(tones, priority),
+            Command::StopSfx => self.speaker.stop(),
+            Command::PlaySample(sample, frequency) => self.samples.play(sample, frequency, self.rate),
+        }
+    }
+
+    /// Keep playing, even if the module had started fading out. Fading in a module
+    /// that is already playing ramps it back up from wherever it got to.
+    fn keep_module(&mut self) {
+        self.fade_target = 1.;
+    }
+
+    /// Switch to the pending module once its thread is ready, or to silence if it
+    /// couldn't be loaded.
+    fn finish_load(&mut self) {
+        let status = match self.pending_load {
+            Some(ref pending) => pending.stream.status(),
+            None => return,
+        };
+        if status == STREAM_LOADING {
+            return;
+        }
+        let pending = self.pending_load.take().unwrap();
+        let module = if status == STREAM_READY { Some(pending.stream) } else { None };
+        let (name, fade_in) = (pending.name, pending.fade_in);
+        let outgoing = mem::replace(&mut self.current_module, module.map(|module| (name, module)));
+        let total = CROSSFADE_MS * self.rate as u64 / 1000;
+        self.crossfade = match outgoing {
+            Some((_, module)) if total > 0 => Some(Crossfade {
+                module,
+                volume: self.volume,
+                remaining: total,
+                total,
+            }),
+            _ => None,
+        };
+        self.volume = if fade_in { 0. } else { 1. };
+        self.fade_target = 1.;
+    }
+
+    /// How much the volume can change between two consecutive samples.
+    fn fade_step(&self) -> f32 {
+        let fade_samples = self.fade_cycles as u64 * self.cycle_ms * self.rate as u64 / 1000;
+        if fade_samples == 0 {
+            1.
+        } else {
+            1. / fade_samples as f32
         }
     }
 }
 
+/// The state of the game's side of the audio system.
+struct Controller {
+    assets: AssetResolver,
+    samples: SampleCache,
+    /// The module most recently asked for.
+    requested: Option<String>,
+    fade_length: (u32, u64),
+    volumes: (f32, f32, f32),
+    silent: bool,
+}
+
+/// The game's handle on the audio system. Every request is passed on to the
+/// `Mixer` without waiting for it. Neither side is realtime, so a plain channel
+/// is fine between them.
 #[derive(Clone)]
-pub struct MusicCallback(Arc<Mutex<MusicData>>);
-unsafe impl Send for MusicCallback {}
+pub struct MusicCallback {
+    commands: Sender<Command>,
+    /// The order the current module is playing, or -1 without a module.
+    order: Arc<AtomicIsize>,
+    state: Rc<RefCell<Controller>>,
+}
 
-impl MusicCallback {
-    pub fn new(world_path: &Path, silent: bool) -> MusicCallback {
-        MusicCallback(Arc::new(Mutex::new(MusicData::new(world_path, silent))))
+/// Renders whatever `MusicCallback` asked for, on a thread of its own.
+pub struct Mixer {
+    commands: Receiver<Command>,
+    order: Arc<AtomicIsize>,
+    recording: Option<(Sender<Vec<f32>>, Receiver<Vec<f32>>)>,
+}
+
+impl Mixer {
+    /// Send a copy of everything that is played to `recording`, reusing the
+    /// buffers that come back through `spare`.
+    pub fn record_to(&mut self, recording: Sender<Vec<f32>>, spare: Receiver<Vec<f32>>) {
+        self.recording = Some((recording, spare));
+    }
+
+    /// Start rendering at `rate` into a ring of `capacity` samples, which the
+    /// returned callback plays.
+    fn start(self, rate: i32, capacity: usize) -> Output {
+        let ring = Arc::new(SampleRing::new(capacity));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let ring = ring.clone();
+            let stop = stop.clone();
+            thread::spawn(move || self.run(rate, &ring, &stop))
+        };
+        Output {
+            ring,
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    fn run(mut self, rate: i32, ring: &SampleRing, stop: &AtomicBool) {
+        // Modules can't be sent between threads, so everything that holds them
+        // is created here.
+        let mut data = MusicData::new(rate);
+        let mut chunk = vec![0.; RENDER_CHUNK_FRAMES * 2];
+        while !stop.load(Ordering::Relaxed) {
+            while let Ok(command) = self.commands.try_recv() {
+                data.handle(command);
+            }
+            data.finish_load();
+            if ring.free() < chunk.len() {
+                thread::sleep(RENDER_WAIT);
+                continue;
+            }
+
+            mix(&mut data, &mut chunk);
+            let order = match data.current_module {
+                Some((_, ref module)) => module.order(),
+                None => -1,
+            };
+            self.order.store(order, Ordering::Relaxed);
+            ring.push(&chunk);
+
+            let stopped = match self.recording {
+                Some((ref recording, ref spare)) => {
+                    let mut buffer = spare.try_recv().unwrap_or_default();
+                    buffer.clear();
+                    buffer.extend_from_slice(&chunk);
+                    recording.send(buffer).is_err()
+                }
+                None => false,
+            };
+            if stopped {
+                self.recording = None;
+            }
+        }
     }
 }
 
-impl AudioEngine for MusicCallback {
-    fn mod_fade_in(&self, file_path: &str) {
-        // TODO: actually fade.
-        self.load_module(file_path);
+/// SDL's audio callback, which plays what the `Mixer` rendered.
+struct Output {
+    ring: Arc<SampleRing>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl AudioCallback for Output {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        let read = self.ring.pop(out);
+        // Play silence if the mixer has fallen behind.
+        for sample in &mut out[read..] {
+            *sample = 0.;
+        }
     }
+}
 
-    fn load_module(&self, file_path: &str) {
-        let file_path = file_path.to_ascii_lowercase();
-        let mut data = self.0.lock().unwrap();
-        if data.current_module.as_ref().map_or(false, |(current, _)| current == &file_path) {
+impl Drop for Output {
+    /// Closing the audio device stops the mixer, which also ends any recording.
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl MusicCallback {
+    pub fn new(world_path: &Path, silent: bool) -> (MusicCallback, Mixer) {
+        let (sender, receiver) = channel();
+        let order = Arc::new(AtomicIsize::new(-1));
+        let music = MusicCallback {
+            commands: sender,
+            order: order.clone(),
+            state: Rc::new(RefCell::new(Controller {
+                assets: AssetResolver::new(world_path),
+                samples: SampleCache::default(),
+                requested: None,
+                fade_length: (DEFAULT_FADE_CYCLES, 48),
+                volumes: (1., 1., 1.),
+                silent,
+            })),
+        };
+        let mixer = Mixer {
+            commands: receiver,
+            order,
+            recording: None,
+        };
+        (music, mixer)
+    }
+
+    fn send(&self, command: Command) {
+        // The mixer only goes away along with the audio device at exit.
+        let _ = self.commands.send(command);
+    }
+
+    /// The directory that the board update loads other world files from.
+    pub fn world_path(&self) -> PathBuf {
+        self.state.borrow().assets.root().to_owned()
+    }
+
+    pub fn set_world_path(&self, world_path: &Path) {
+        let mut state = self.state.borrow_mut();
+        state.assets = AssetResolver::new(world_path);
+        state.samples.clear();
+    }
+
+    /// Set how long fades last, in game cycles of `cycle_ms` milliseconds each.
+    pub fn set_fade_length(&self, fade_cycles: u32, cycle_ms: u64) {
+        let mut state = self.state.borrow_mut();
+        if state.fade_length != (fade_cycles, cycle_ms) {
+            state.fade_length = (fade_cycles, cycle_ms);
+            self.send(Command::SetFadeLength { fade_cycles, cycle_ms });
+        }
+    }
+
+    pub fn set_volumes(&self, volumes: (f32, f32, f32)) {
+        let mut state = self.state.borrow_mut();
+        if state.volumes != volumes {
+            state.volumes = volumes;
+            self.send(Command::SetVolumes(volumes));
+        }
+    }
+
+    /// Play a sound effect written in PLAY syntax on the PC speaker. Any sample
+    /// named between a pair of `&`s starts playing straight away.
+    pub fn play_sfx(&self, notes: &[u8], priority: u8) {
+        if self.state.borrow().silent {
             return;
         }
-        let module_path = Path::join(&data.world_path, &file_path);
-        let module_data = match File::open(&module_path) {
-            Ok(mut file) => {
-                let mut v = vec![];
-                match file.read_to_end(&mut v) {
-                    Ok(_) => match Module::create_from_memory(&v[..], Logger::StdErr, &[]) {
-                        Ok(m) => Some((file_path, m)),
-                        Err(()) => {
-                            eprintln!("Error loading {}", module_path.display());
-                            None
-                        }
-                    },
-                    Err(e) => {
-                        if !file_path.is_empty() {
-                            eprintln!("Error opening {} ({})", module_path.display(), e);
-                        }
-                        None
-                    }
-                }
+        let mut parts = notes.split(|&c| c == b'&');
+        let mut tones = parse_play(parts.next().unwrap_or(&[]));
+        while let Some(file_path) = parts.next() {
+            self.play_sample(&String::from_utf8_lossy(file_path), 0);
+            tones.extend(parse_play(parts.next().unwrap_or(&[])));
+        }
+        self.send(Command::PlaySfx(tones, priority));
+    }
+
+    /// Play a .sam or .wav file from the world's directory at `frequency`, or at
+    /// its natural rate if that is 0.
+    pub fn play_sample(&self, file_path: &str, frequency: u32) {
+        let sample = {
+            let state = &mut *self.state.borrow_mut();
+            if state.silent {
+                return;
             }
-            Err(e) => {
-                eprintln!("Error opening {} ({})", module_path.display(), e);
-                None
+            state.samples.get(&mut state.assets, file_path)
+        };
+        if let Some(sample) = sample {
+            self.send(Command::PlaySample(sample, frequency));
+        }
+    }
+
+    pub fn stop_sfx(&self) {
+        self.send(Command::StopSfx);
+    }
+
+    /// Start reading a module on a worker thread. Whatever is playing now keeps
+    /// playing until the new module is ready.
+    fn request_module(&self, file_path: &str, fade_in: bool) {
+        let name = file_path.to_ascii_lowercase();
+        let module_path = {
+            let mut state = self.state.borrow_mut();
+            if state.silent {
+                return;
+            }
+            if state.requested.as_ref() == Some(&name) {
+                self.send(Command::KeepModule);
+                return;
             }
+            state.requested = Some(name.clone());
+            state.assets.resolve(&name)
         };
-        if !data.silent {
-            data.current_module = module_data;
+
+        self.send(Command::WantModule { name, path: module_path, fade_in });
+    }
+
+    /// The name and current order of the module that is playing, if any.
+    pub fn current_module(&self) -> Option<(String, i32)> {
+        let order = self.order.load(Ordering::Relaxed);
+        if order < 0 {
+            return None;
         }
-        data.new_position = None;
+        self.state.borrow().requested.clone().map(|name| (name, order as i32))
+    }
+}
+
+fn read_module(module_path: &Path, name: &str) -> Option<Vec<u8>> {
+    let mut file = match File::open(module_path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Error opening {} ({})", module_path.display(), e);
+            return None;
+        }
+    };
+    let mut v = vec![];
+    if let Err(e) = file.read_to_end(&mut v) {
+        // Boards without music ask for a module with no name.
+        if !name.is_empty() {
+            eprintln!("Error opening {} ({})", module_path.display(), e);
+        }
+        return None;
+    }
+    Some(v)
+}
+
+fn decode_module(data: &[u8], name: &str) -> Option<Module> {
+    match Module::create_from_memory(data, Logger::StdErr, &[]) {
+        Ok(m) => Some(m),
+        Err(()) => {
+            eprintln!("Error loading {}", name);
+            None
+        }
+    }
+}
+
+impl AudioEngine for MusicCallback {
+    fn mod_fade_in(&self, file_path: &str) {
+        self.request_module(file_path, true);
+    }
+
+    fn load_module(&self, file_path: &str) {
+        self.request_module(file_path, false);
     }
 
     fn end_module(&self) {
-        let mut data = self.0.lock().unwrap();
-        data.current_module = None;
+        self.state.borrow_mut().requested = None;
+        self.send(Command::EndModule);
     }
 
+    /// The module keeps playing until it has faded out completely.
     fn mod_fade_out(&self) {
-        // TODO: actually fade.
-        self.end_module();
+        // Once the fade is over, asking for the same module has to start it again.
+        self.state.borrow_mut().requested = None;
+        self.send(Command::FadeOut);
     }
 
     fn set_mod_order(&self, order: i32) {
-        let mut data = self.0.lock().unwrap();
-        data.new_position = Some(order);
+        self.send(Command::SetOrder(order));
+    }
+
+    /// A robot's PLAY command.
+    #[cfg(feature = "robot-audio")]
+    fn play_sound(&self, notes: &[u8]) {
+        self.play_sfx(notes, ROBOT_PRIORITY);
+    }
+
+    /// A robot's END PLAY command.
+    #[cfg(feature = "robot-audio")]
+    fn end_sound(&self) {
+        self.stop_sfx();
+    }
+
+    /// A robot's SAM command, which gives a period rather than a frequency, with 0
+    /// meaning the sample's natural rate.
+    #[cfg(feature = "robot-audio")]
+    fn load_sample(&self, period: u32, file_path: &str) {
+        let frequency = if period == 0 { 0 } else { SAM_CLOCK_HZ / period / 2 };
+        self.play_sample(file_path, frequency);
     }
 }
 
-impl AudioCallback for MusicCallback {
-    type Channel = f32;
+/// Render the next `out.len()` samples of everything that is playing.
+fn mix(data: &mut MusicData, out: &mut [f32]) {
+    let rate = data.rate;
+    if let Some((_, ref module)) = data.current_module {
+        module.read(out);
+    } else {
+        for i in out.iter_mut() {
+            *i = 0.;
+        }
+    }
 
-    fn callback(&mut self, out: &mut [f32]) {
-        let mut data = self.0.lock().unwrap();
-        let rate = data.rate;
-        let position = data.new_position.take();
-        if let Some((_, ref mut module)) = data.current_module {
-            if let Some(new_position) = position {
-                module.set_position_order_row(new_position, 0);
+    // Fades only apply to the incoming module; the outgoing one already has
+    // its own ramp down.
+    let fading = data.volume != 1. || data.fade_target != 1.;
+    if fading && data.current_module.is_some() {
+        apply_fade(data, out);
+    }
+
+    if let Some(ref mut crossfade) = data.crossfade {
+        data.scratch.resize(out.len(), 0.);
+        crossfade.module.read(&mut data.scratch);
+        for (frame, old_frame) in out.chunks_mut(2).zip(data.scratch.chunks(2)) {
+            let progress = crossfade.remaining as f32 / crossfade.total as f32;
+            for (sample, &old) in frame.iter_mut().zip(old_frame) {
+                *sample = *sample * (1. - progress) + old * progress * crossfade.volume;
             }
-            module.read_interleaved_float_stereo(rate, out);
+            crossfade.remaining = crossfade.remaining.saturating_sub(1);
+        }
+    }
+    if data.crossfade.as_ref().map_or(false, |crossfade| crossfade.remaining == 0) {
+        data.crossfade = None;
+    }
+    let (music_volume, sample_volume, speaker_volume) = data.volumes;
+    if music_volume != 1. {
+        for sample in out.iter_mut() {
+            *sample *= music_volume;
+        }
+    }
+
+    data.speaker.mix(rate, out, speaker_volume);
+    data.samples.mix(out, sample_volume);
+}
+
+fn apply_fade(data: &mut MusicData, out: &mut [f32]) {
+    let step = data.fade_step();
+    let target = data.fade_target;
+    let mut volume = data.volume;
+    for frame in out.chunks_mut(2) {
+        volume = if volume < target {
+            (volume + step).min(target)
         } else {
-            for i in out {
-                *i = 0.;
-            }
+            (volume - step).max(target)
+        };
+        for sample in frame {
+            *sample *= volume;
         }
     }
+    data.volume = volume;
+    if volume == 0. && target == 0. {
+        data.current_module = None;
+    }
 }
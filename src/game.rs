@@ -1,5 +1,19 @@
-use crate::{GameState, PoppedData, StateChange, SdlRenderer};
+use crate::{GameState, PoppedData, StateChange, SdlRenderer, read_world};
 use crate::audio::MusicCallback;
+use crate::history::MessageHistory;
+use crate::inventory::InventoryState;
+use crate::menu::{BoardPickerState, LoadBrowserState, PauseChoice, PauseMenuState, navigate_list};
+use crate::save::{
+    LOAD_GAME_COUNTER, NUM_SLOTS, SAVE_GAME_COUNTER, load_game, save_game, save_path, take_robot_request,
+};
+use crate::settings::{Settings, SettingsState, Volumes};
+use crate::sfx::{
+    BUILT_IN_PRIORITY, SFX_LOCKED, SFX_OPEN_DOOR, SFX_OUT_OF_AMMO, SFX_SHOOT, SFX_TRANSPORT, SFX_UNLOCK,
+};
+use crate::player::{
+    PlayerStatus, apply_potion, damage_from, enemies_in_line, hurt_by_aimed_bullets, hurt_by_enemies, hurt_player,
+    is_enemy,
+};
 use libmzx::audio::AudioEngine;
 use libmzx::board::{enter_board, run_board_update};
 use libmzx::keyboard::Key;
@@ -10,21 +24,30 @@ use libmzx::{
     bullet_param, BulletType, adjust_coordinate, MessageBoxLineType, Robot, adjust_coordinate_diff,
 };
 use libmzx::board::{NORTH, SOUTH, EAST, WEST, ExternalStateChange, LabelAction, move_level, put_at, move_level_to, reset_view};
-use num_traits::ToPrimitive;
+use num_traits::{FromPrimitive, ToPrimitive};
+use rand::{self, Rng};
 use sdl2::event::Event;
 use sdl2::keyboard::{Keycode, Mod};
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
 use sdl2::render::Canvas;
 use sdl2::video::Window;
-use std::path::Path;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const CHAR_WIDTH: i32 = 8;
+const CHAR_HEIGHT: i32 = 14;
 
 fn render_game(
     world: &World,
     board_id: usize,
     canvas: &mut Canvas<Window>,
     is_title_screen: bool,
+    blind: bool,
 ) {
-    let mut renderer = SdlRenderer { canvas };
     let (ref board, ref robots) = world.boards[board_id];
+    let mut renderer = SdlRenderer { canvas: &mut *canvas };
     render(
         &world.state,
         (
@@ -37,16 +60,171 @@ fn render_game(
         &mut renderer,
         is_title_screen,
     );
+
+    if blind {
+        draw_blindness(board, canvas);
+    }
+}
+
+/// Black out everything in the viewport except the player.
+fn draw_blindness(board: &Board, canvas: &mut Canvas<Window>) {
+    let viewport_x = board.upper_left_viewport.0 as i32 * CHAR_WIDTH;
+    let viewport_y = board.upper_left_viewport.1 as i32 * CHAR_HEIGHT;
+    let viewport_w = board.viewport_size.0 as i32 * CHAR_WIDTH;
+    let viewport_h = board.viewport_size.1 as i32 * CHAR_HEIGHT;
+    let player_x = viewport_x + (board.player_pos.0 as i32 - board.scroll_offset.0 as i32) * CHAR_WIDTH;
+    let player_y = viewport_y + (board.player_pos.1 as i32 - board.scroll_offset.1 as i32) * CHAR_HEIGHT;
+
+    let rects = [
+        (viewport_x, viewport_y, viewport_w, player_y - viewport_y),
+        (viewport_x, player_y + CHAR_HEIGHT, viewport_w, viewport_y + viewport_h - player_y - CHAR_HEIGHT),
+        (viewport_x, player_y, player_x - viewport_x, CHAR_HEIGHT),
+        (player_x + CHAR_WIDTH, player_y, viewport_x + viewport_w - player_x - CHAR_WIDTH, CHAR_HEIGHT),
+    ];
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    for &(x, y, w, h) in &rects {
+        if w > 0 && h > 0 {
+            canvas.fill_rect(Rect::new(x, y, w as u32, h as u32)).unwrap();
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum TitleChoice {
+    Play,
+    Restore,
+    Settings,
+    Help,
+    StartAtBoard,
+    Quit,
+}
+
+const TITLE_ITEMS: &[(Keycode, &str, TitleChoice)] = &[
+    (Keycode::P, "P  Play game", TitleChoice::Play),
+    (Keycode::R, "R  Restore game", TitleChoice::Restore),
+    (Keycode::S, "S  Settings", TitleChoice::Settings),
+    (Keycode::H, "H  Help", TitleChoice::Help),
+    (Keycode::D, "D  Start at board", TitleChoice::StartAtBoard),
+    (Keycode::Escape, "Esc  Quit", TitleChoice::Quit),
+];
+
+const HELP_TEXT: &[&str] = &[
+    "Arrow keys    Move",
+    "Space+arrow   Shoot",
+    "Enter         Status",
+    "Escape        Pause menu",
+    "Ctrl+1-9      Quicksave to a slot",
+    "F9            Quicksave to the last slot",
+    "Alt+1-9       Quickload from a slot",
+    "F11           Quickload from the last slot",
+    "F8            Message log",
+    "F10           Load a saved game",
+    "",
+    "Robots save and load by setting the save_game or",
+    "load_game counter to a slot from 1 to 9, or to any",
+    "other number for the main save. MegaZeux's form,",
+    "which takes a file name, isn't supported, and",
+    "saves can't be opened by MegaZeux itself.",
+];
+
+pub(crate) struct TitleState {
+    music: MusicCallback,
+    settings: Settings,
+    world_file: PathBuf,
+    reload_world: bool,
+    /// The world to switch to, when it has been loaded already.
+    loaded_world: Option<World>,
+    menu_pos: usize,
+    pending_change: Option<StateChange>,
+}
+
+impl TitleState {
+    pub fn new(music: MusicCallback, settings: Settings, world_file: &Path) -> TitleState {
+        TitleState {
+            music,
+            settings,
+            world_file: world_file.to_owned(),
+            reload_world: false,
+            loaded_world: None,
+            menu_pos: 0,
+            pending_change: None,
+        }
+    }
+
+    /// A title screen that starts over with a freshly loaded copy of the world,
+    /// discarding all progress made so far.
+    pub fn reloaded(music: MusicCallback, settings: Settings, world_file: &Path) -> TitleState {
+        TitleState {
+            reload_world: true,
+            ..TitleState::new(music, settings, world_file)
+        }
+    }
+
+    /// A title screen that switches to `world`, which was just loaded from
+    /// `world_file`.
+    pub fn loaded(music: MusicCallback, settings: Settings, world_file: &Path, world: World) -> TitleState {
+        TitleState {
+            loaded_world: Some(world),
+            ..TitleState::reloaded(music, settings, world_file)
+        }
+    }
+
+    /// Starting at an arbitrary board is only offered in developer mode.
+    fn menu_items(&self) -> Vec<(Keycode, &'static str, TitleChoice)> {
+        let developer_mode = self.settings.get().developer_mode;
+        TITLE_ITEMS
+            .iter()
+            .cloned()
+            .filter(|&(_, _, choice)| developer_mode || choice != TitleChoice::StartAtBoard)
+            .collect()
+    }
+
+    fn choose(&self, choice: TitleChoice) -> Option<StateChange> {
+        Some(match choice {
+            TitleChoice::Play => StateChange::Replace(Box::new(
+                PlayState::new(self.music.clone(), self.settings.clone(), &self.world_file, None)
+            )),
+            TitleChoice::Restore => StateChange::Push(Box::new(LoadBrowserState::new(&self.world_file))),
+            TitleChoice::Settings => StateChange::Push(Box::new(SettingsState::new(self.settings.clone()))),
+            TitleChoice::Help => {
+                let lines = HELP_TEXT
+                    .iter()
+                    .map(|&text| MessageBoxLine::Text(text.into(), MessageBoxLineType::Plain))
+                    .collect();
+                StateChange::Push(Box::new(
+                    MessageBoxState::new("Help".into(), lines, MessageBoxSource::Sign)
+                ))
+            }
+            TitleChoice::StartAtBoard => StateChange::Push(Box::new(BoardPickerState::new())),
+            TitleChoice::Quit => StateChange::PopCurrent(None),
+        })
+    }
 }
 
-pub(crate) struct TitleState(pub MusicCallback);
 impl GameState for TitleState {
     fn init(&mut self, world: &mut World, board_id: &mut usize) {
+        if self.reload_world {
+            let fresh_world = match self.loaded_world.take() {
+                Some(world) => Ok(world),
+                None => read_world(&self.world_file),
+            };
+            match fresh_world {
+                Ok(fresh_world) => {
+                    *world = fresh_world;
+                    *board_id = 0;
+                    self.music.end_module();
+                    self.music.stop_sfx();
+                    self.music.set_world_path(self.world_file.parent().unwrap());
+                }
+                Err(e) => warn!("{}", e),
+            }
+        }
+
         let (ref mut board, ref mut robots) = world.boards[*board_id];
         let player_pos = board.player_pos;
         enter_board(
             &mut world.state,
-            &self.0,
+            &self.music,
             board,
             player_pos,
             robots,
@@ -56,7 +234,15 @@ impl GameState for TitleState {
         );
     }
 
-    fn popped(&mut self, _world: &mut World, _board_id: usize, _data: PoppedData) {
+    fn popped(&mut self, _world: &mut World, _board_id: usize, data: PoppedData) {
+        let play_state = match data {
+            PoppedData::LoadSave(slot) =>
+                PlayState::restoring(self.music.clone(), self.settings.clone(), &self.world_file, slot),
+            PoppedData::StartBoard(board_id) =>
+                PlayState::new(self.music.clone(), self.settings.clone(), &self.world_file, Some(board_id)),
+            _ => return,
+        };
+        self.pending_change = Some(StateChange::Replace(Box::new(play_state)));
     }
 
     fn input(
@@ -64,11 +250,19 @@ impl GameState for TitleState {
         event: Event,
         _input_state: &mut InputState,
     ) -> Option<StateChange> {
+        let items = self.menu_items();
+        if navigate_list(&event, &mut self.menu_pos, items.len()) {
+            return None;
+        }
         match event {
-            Event::KeyDown {keycode: Some(Keycode::Escape), ..} =>
-                Some(StateChange::PopCurrent(None)),
-            Event::KeyDown {keycode: Some(Keycode::P), ..} =>
-                Some(StateChange::Replace(Box::new(PlayState::new(self.0.clone(), None)))),
+            Event::KeyDown {keycode: Some(Keycode::Return), ..} =>
+                self.choose(items[self.menu_pos].2),
+            Event::KeyDown {keycode: Some(Keycode::F1), ..} =>
+                self.choose(TitleChoice::Help),
+            Event::KeyDown {keycode: Some(keycode), ..} => {
+                let choice = items.iter().find(|&&(key, _, _)| key == keycode).map(|&(_, _, c)| c);
+                choice.and_then(|choice| self.choose(choice))
+            }
             _ => None,
         }
     }
@@ -76,14 +270,20 @@ impl GameState for TitleState {
     fn tick(
         &mut self,
         world: &mut World,
-        world_path: &Path,
         input_state: &InputState,
         counters: &mut Counters,
         boards: &[ByteString],
         board_id: &mut usize,
     ) -> Option<StateChange> {
+        if self.pending_change.is_some() {
+            return self.pending_change.take();
+        }
+        // Counters aren't part of the world, so they can only be reset once we get here.
+        if mem::replace(&mut self.reload_world, false) {
+            *counters = Counters::new();
+        }
         tick_game_loop(
-            world, &self.0, world_path, input_state, counters, boards, board_id, &mut false,
+            world, &self.music, input_state, counters, boards, board_id, &mut false, None, None,
         )
     }
 
@@ -93,23 +293,146 @@ impl GameState for TitleState {
         board_id: usize,
         canvas: &mut Canvas<Window>,
     ) {
-        render_game(world, board_id, canvas, true);
+        render_game(world, board_id, canvas, true, false);
+        let lines: Vec<_> = self.menu_items()
+            .iter()
+            .map(|&(_, text, _)| MessageBoxLine::Text(text.into(), MessageBoxLineType::Plain))
+            .collect();
+        let mut renderer = SdlRenderer { canvas };
+        draw_messagebox(&world.state, &"".into(), &lines, self.menu_pos, &mut renderer);
     }
 }
 
 pub struct PlayState {
     music: MusicCallback,
+    settings: Settings,
+    world_file: PathBuf,
     accept_player_input: bool,
     starting_board: Option<usize>,
+    player_status: PlayerStatus,
+    history: MessageHistory,
+    /// The volumes last written to the volume counters.
+    volume_counters: Option<Volumes>,
+    pending_change: Option<StateChange>,
+    pending_save: Option<SaveAction>,
+    quicksave_slot: u8,
+    previous_play_time: Duration,
+    session_start: Instant,
+}
+
+/// Saving and loading wait for the next tick, which has access to the whole session.
+enum SaveAction {
+    Save(Option<u8>),
+    Load(Option<u8>),
 }
 impl PlayState {
-    pub fn new(music: MusicCallback, starting_board: Option<usize>) -> PlayState {
+    pub fn new(
+        music: MusicCallback,
+        settings: Settings,
+        world_file: &Path,
+        starting_board: Option<usize>,
+    ) -> PlayState {
         PlayState {
             music,
+            settings,
+            world_file: world_file.to_owned(),
             starting_board,
             accept_player_input: true,
+            player_status: PlayerStatus::default(),
+            history: MessageHistory::default(),
+            volume_counters: None,
+            pending_change: None,
+            pending_save: None,
+            quicksave_slot: 1,
+            previous_play_time: Duration::from_secs(0),
+            session_start: Instant::now(),
         }
     }
+
+    /// A game that immediately restores the given save slot once it starts.
+    pub fn restoring(
+        music: MusicCallback,
+        settings: Settings,
+        world_file: &Path,
+        slot: Option<u8>,
+    ) -> PlayState {
+        PlayState {
+            pending_save: Some(SaveAction::Load(slot)),
+            ..PlayState::new(music, settings, world_file, None)
+        }
+    }
+
+    fn play_time(&self) -> Duration {
+        self.previous_play_time + self.session_start.elapsed()
+    }
+
+    fn save(&self, world: &mut World, board_id: usize, counters: &Counters, slot: Option<u8>) {
+        let path = save_path(&self.world_file, slot);
+        let effects = &self.player_status.effects;
+        let message = match save_game(&path, world, board_id, counters, effects, &self.music, self.play_time()) {
+            Ok(()) => "Game saved.",
+            Err(e) => {
+                warn!("Error saving {} ({})", path.display(), e);
+                "Unable to save the game."
+            }
+        };
+        world.boards[board_id].0.set_message_line(message.into());
+    }
+
+    fn load(&mut self, world: &mut World, board_id: &mut usize, counters: &mut Counters, slot: Option<u8>) {
+        let path = save_path(&self.world_file, slot);
+        let mut fresh_world = match read_world(&self.world_file) {
+            Ok(world) => world,
+            Err(e) => {
+                warn!("{}", e);
+                world.boards[*board_id].0.set_message_line("Unable to load the game.".into());
+                return;
+            }
+        };
+        let mut saved_board_id = *board_id;
+        let mut effects = Default::default();
+        match load_game(&path, &mut fresh_world, &mut saved_board_id, counters, &mut effects, &self.music) {
+            Ok(play_time) => {
+                self.previous_play_time = play_time;
+                self.session_start = Instant::now();
+            }
+            Err(e) => {
+                warn!("Error loading {} ({})", path.display(), e);
+                world.boards[*board_id].0.set_message_line("Unable to load the game.".into());
+                return;
+            }
+        }
+
+        *world = fresh_world;
+        *board_id = saved_board_id;
+        self.player_status = PlayerStatus { effects, ..PlayerStatus::default() };
+        let (ref mut board, ref mut robots) = world.boards[*board_id];
+        let pos = board.player_pos;
+        enter_board(&mut world.state, &self.music, board, pos, robots, &mut world.global_robot, LabelAction::RunJustLoadedAndJustEntered, true);
+        board.set_message_line("Game loaded.".into());
+    }
+
+    /// Put the current board back the way it was when the world was loaded, with
+    /// the player where they entered it.
+    fn restart_board(&mut self, world: &mut World, board_id: usize) {
+        let mut fresh_world = match read_world(&self.world_file) {
+            Ok(world) => world,
+            Err(e) => {
+                warn!("{}", e);
+                world.boards[board_id].0.set_message_line("Unable to restart the board.".into());
+                return;
+            }
+        };
+        world.boards[board_id] = fresh_world.boards.swap_remove(board_id);
+
+        let (ref mut board, ref mut robots) = world.boards[board_id];
+        let pos = match self.player_status.entrance {
+            Some((id, pos)) if id == board_id => pos,
+            _ => board.player_pos,
+        };
+        self.player_status.sliding = None;
+        enter_board(&mut world.state, &self.music, board, pos, robots, &mut world.global_robot, LabelAction::RunJustEntered, true);
+    }
 }
 
 impl GameState for PlayState {
@@ -140,6 +463,28 @@ impl GameState for PlayState {
                 board.player_pos = pos;
                 reset_view(board);
             }
+            PoppedData::PauseMenu(choice) => match choice {
+                PauseChoice::Resume => (),
+                PauseChoice::Save => self.pending_save = Some(SaveAction::Save(None)),
+                PauseChoice::Load => {
+                    self.pending_change = Some(StateChange::Push(Box::new(
+                        LoadBrowserState::new(&self.world_file)
+                    )));
+                }
+                PauseChoice::Settings => {
+                    self.pending_change = Some(StateChange::Push(Box::new(
+                        SettingsState::new(self.settings.clone())
+                    )));
+                }
+                PauseChoice::RestartBoard => self.restart_board(world, board_id),
+                PauseChoice::QuitToTitle => {
+                    self.pending_change = Some(StateChange::Replace(Box::new(
+                        TitleState::reloaded(self.music.clone(), self.settings.clone(), &self.world_file)
+                    )));
+                }
+            },
+            PoppedData::LoadSave(slot) => self.pending_save = Some(SaveAction::Load(slot)),
+            PoppedData::StartBoard(_) => (),
         }
     }
 
@@ -150,7 +495,40 @@ impl GameState for PlayState {
     ) -> Option<StateChange> {
         match event {
             Event::KeyDown {keycode: Option::Some(Keycode::Escape), ..} =>
-                Some(StateChange::PopCurrent(None)),
+                Some(StateChange::Push(Box::new(PauseMenuState::new()))),
+            Event::KeyDown {keycode: Option::Some(Keycode::Return), ..} =>
+                Some(StateChange::Push(Box::new(
+                    InventoryState::new(self.player_status.effects.active())
+                ))),
+            Event::KeyDown {keycode: Option::Some(Keycode::F9), ..} => {
+                self.pending_save = Some(SaveAction::Save(Some(self.quicksave_slot)));
+                None
+            }
+            Event::KeyDown {keycode: Option::Some(Keycode::F8), ..} =>
+                Some(StateChange::Push(Box::new(
+                    MessageBoxState::new("Message log".into(), self.history.lines(), MessageBoxSource::Sign)
+                        .scrolled_to_end()
+                ))),
+            Event::KeyDown {keycode: Option::Some(Keycode::F10), ..} =>
+                Some(StateChange::Push(Box::new(LoadBrowserState::new(&self.world_file)))),
+            Event::KeyDown {keycode: Option::Some(Keycode::F11), ..} => {
+                self.pending_save = Some(SaveAction::Load(Some(self.quicksave_slot)));
+                None
+            }
+            Event::KeyDown {keycode: Option::Some(keycode), keymod, ..}
+                if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) && quicksave_slot_for_key(keycode).is_some() =>
+            {
+                self.quicksave_slot = quicksave_slot_for_key(keycode).unwrap();
+                self.pending_save = Some(SaveAction::Save(Some(self.quicksave_slot)));
+                None
+            }
+            Event::KeyDown {keycode: Option::Some(keycode), keymod, ..}
+                if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) && quicksave_slot_for_key(keycode).is_some() =>
+            {
+                self.quicksave_slot = quicksave_slot_for_key(keycode).unwrap();
+                self.pending_save = Some(SaveAction::Load(Some(self.quicksave_slot)));
+                None
+            }
             Event::KeyDown {timestamp, keycode, keymod, repeat, ..} => {
                 let _ = handle_key_input(
                     input_state,
@@ -180,15 +558,43 @@ impl GameState for PlayState {
     fn tick(
         &mut self,
         world: &mut World,
-        world_path: &Path,
         input_state: &InputState,
         counters: &mut Counters,
         boards: &[ByteString],
         board_id: &mut usize,
     ) -> Option<StateChange> {
-        tick_game_loop(
-            world, &self.music, world_path, input_state, counters, boards, board_id, &mut self.accept_player_input
-        )
+        if self.pending_change.is_some() {
+            return self.pending_change.take();
+        }
+        match self.pending_save.take() {
+            Some(SaveAction::Save(slot)) => self.save(world, *board_id, counters, slot),
+            Some(SaveAction::Load(slot)) => self.load(world, board_id, counters, slot),
+            None => (),
+        }
+        let change = tick_game_loop(
+            world,
+            &self.music,
+            input_state,
+            counters,
+            boards,
+            board_id,
+            &mut self.accept_player_input,
+            Some(&mut self.player_status),
+            Some(&mut self.history),
+        );
+        self.settings.sync_volume_counters(counters, &mut self.volume_counters);
+        if let Some(slot) = take_robot_request(counters, SAVE_GAME_COUNTER) {
+            self.pending_save = Some(SaveAction::Save(slot));
+        }
+        if let Some(slot) = take_robot_request(counters, LOAD_GAME_COUNTER) {
+            self.pending_save = Some(SaveAction::Load(slot));
+        }
+        if self.player_status.game_over {
+            return Some(StateChange::Replace(Box::new(
+                GameOverState::new(self.music.clone(), self.settings.clone(), &self.world_file)
+            )));
+        }
+        change
     }
 
     fn render(
@@ -197,7 +603,86 @@ impl GameState for PlayState {
         board_id: usize,
         canvas: &mut Canvas<Window>,
     ) {
-        render_game(world, board_id, canvas, false);
+        render_game(world, board_id, canvas, false, self.player_status.effects.blind > 0);
+    }
+}
+
+/// Number of cycles the game over screen is shown before returning to the title screen.
+const GAME_OVER_CYCLES: u32 = 150;
+
+pub(crate) struct GameOverState {
+    music: MusicCallback,
+    settings: Settings,
+    world_file: PathBuf,
+    remaining: u32,
+}
+
+impl GameOverState {
+    pub fn new(music: MusicCallback, settings: Settings, world_file: &Path) -> GameOverState {
+        GameOverState {
+            music,
+            settings,
+            world_file: world_file.to_owned(),
+            remaining: GAME_OVER_CYCLES,
+        }
+    }
+
+    fn return_to_title(&self) -> Option<StateChange> {
+        Some(StateChange::Replace(Box::new(
+            TitleState::reloaded(self.music.clone(), self.settings.clone(), &self.world_file)
+        )))
+    }
+}
+
+impl GameState for GameOverState {
+    fn init(&mut self, _world: &mut World, _board_id: &mut usize) {
+        self.music.mod_fade_out();
+    }
+
+    fn popped(&mut self, _world: &mut World, _board_id: usize, _data: PoppedData) {
+    }
+
+    fn input(
+        &mut self,
+        event: Event,
+        _input_state: &mut InputState,
+    ) -> Option<StateChange> {
+        match event {
+            Event::KeyDown {keycode: Some(Keycode::Escape), ..} |
+            Event::KeyDown {keycode: Some(Keycode::Return), ..} |
+            Event::KeyDown {keycode: Some(Keycode::Space), ..} =>
+                self.return_to_title(),
+            _ => None,
+        }
+    }
+
+    fn tick(
+        &mut self,
+        _world: &mut World,
+        _input_state: &InputState,
+        _counters: &mut Counters,
+        _boards: &[ByteString],
+        _board_id: &mut usize,
+    ) -> Option<StateChange> {
+        self.remaining = self.remaining.saturating_sub(1);
+        if self.remaining == 0 {
+            return self.return_to_title();
+        }
+        None
+    }
+
+    fn render(
+        &mut self,
+        world: &World,
+        board_id: usize,
+        canvas: &mut Canvas<Window>,
+    ) {
+        render_game(world, board_id, canvas, false, false);
+        let mut renderer = SdlRenderer { canvas };
+        let lines = vec![
+            MessageBoxLine::Text(b"Game Over".to_vec().into(), MessageBoxLineType::Plain),
+        ];
+        draw_messagebox(&world.state, &"".into(), &lines, 0, &mut renderer);
     }
 }
 
@@ -272,6 +757,28 @@ fn handle_key_input(
     None
 }
 
+/// Ctrl plus a number key quicksaves to the slot with that number, and Alt plus
+/// a number key loads it.
+fn quicksave_slot_for_key(keycode: Keycode) -> Option<u8> {
+    let slot = match keycode {
+        Keycode::Num1 => 1,
+        Keycode::Num2 => 2,
+        Keycode::Num3 => 3,
+        Keycode::Num4 => 4,
+        Keycode::Num5 => 5,
+        Keycode::Num6 => 6,
+        Keycode::Num7 => 7,
+        Keycode::Num8 => 8,
+        Keycode::Num9 => 9,
+        _ => return None,
+    };
+    if slot <= NUM_SLOTS {
+        Some(slot)
+    } else {
+        None
+    }
+}
+
 enum InputResult {
     ExitBoard(CardinalDirection),
     Collide(Coordinate<u16>),
@@ -390,10 +897,12 @@ fn key_from_keycode(keycode: Keycode) -> Option<Key> {
 
 fn process_input(
     board: &mut Board,
-    robots: &mut [Robot],
+    robots: &mut Vec<Robot>,
+    global_robot: &mut Robot,
     input_state: &InputState,
     world_state: &mut WorldState,
     allow_move_player: &mut bool,
+    pushed: &mut Vec<(Coordinate<u16>, (i8, i8))>,
 ) -> Option<InputResult> {
     world_state.key_pressed = input_state.pressed_keycode.and_then(key_from_keycode);
 
@@ -465,10 +974,23 @@ fn process_input(
     );
     if new_player_pos != player_pos {
         let thing = board.thing_at(&new_player_pos).unwrap();
-        if thing.is_pushable() {
-            if let Some(pushed_pos) = adjust_coordinate_diff(new_player_pos, board, xdiff as i16, ydiff as i16) {
-                move_level_to(board, robots, &new_player_pos, &pushed_pos, &mut *world_state.update_done).unwrap();
-            } else {
+        let walkable_hazard = thing == Thing::Lava || thing == Thing::Fire;
+        let must_touch = match thing {
+            Thing::Potion | Thing::Ring | Thing::Transport => true,
+            _ => damage_from(thing).is_some() && !walkable_hazard,
+        };
+        if thing == Thing::Sensor {
+            // The player stands on sensors the way MegaZeux does, with the sensor
+            // kept underneath until they step off again.
+            let sensor = *board.level_at(&new_player_pos).unwrap();
+            *board.under_at_mut(&new_player_pos).unwrap() = sensor;
+            *board.level_at_mut(&new_player_pos).unwrap() = (Thing::Space.to_u8().unwrap(), 0x07, 0);
+            send_sensor_label(board, robots, global_robot, sensor.2, "sensoron");
+        } else if must_touch {
+            return Some(InputResult::Collide(new_player_pos));
+        } else if thing.is_pushable() {
+            let dir = (xdiff, ydiff);
+            if !clear_path(board, robots, global_robot, &new_player_pos, dir, &mut *world_state.update_done, pushed) {
                 return Some(InputResult::Collide(new_player_pos));
             }
         } else if thing.is_solid() {
@@ -491,27 +1013,502 @@ fn process_input(
     None
 }
 
+const DOOR_FIRST_MOVEMENT: &[(i8, i8)] = &[
+    NORTH, WEST, NORTH, EAST, SOUTH, WEST, SOUTH, EAST,
+];
+
+/// The upper five bits of an open door's param count the cycles since it opened.
+const DOOR_TIMER_MASK: u8 = 0xF8;
+
+/// Items that are destroyed rather than blocking when a push chain runs into them.
+fn is_crushable(thing: Thing) -> bool {
+    match thing {
+        Thing::Gem | Thing::MagicGem | Thing::Health | Thing::Ring | Thing::Potion |
+        Thing::Energizer | Thing::Goop | Thing::Ammo | Thing::Bomb | Thing::Key |
+        Thing::Coin | Thing::Pouch => true,
+        _ => false,
+    }
+}
+
+/// Send `label` to the robot that the sensor with the given param reports to.
+fn send_sensor_label(
+    board: &Board,
+    robots: &mut Vec<Robot>,
+    global_robot: &mut Robot,
+    sensor_param: u8,
+    label: &str,
+) {
+    let sensor = match board.sensors.get((sensor_param as usize).wrapping_sub(1)) {
+        Some(sensor) => sensor,
+        None => {
+            warn!("no sensor with param {}", sensor_param);
+            return;
+        }
+    };
+    let label = ByteString::from(label);
+    let mut robots = Robots::new(robots, global_robot);
+    robots.foreach(|robot, _id| {
+        if robot.name.eq_ignore_ascii_case(&sensor.robot_to_mesg) {
+            send_robot_to_label(robot, EvaluatedByteString::no_eval_needed(label.clone()));
+        }
+    });
+}
+
+/// Make room at `pos` by pushing the chain of pushable things starting there one
+/// step in direction `dir`, crushing any crushable item at the end of the chain.
+/// Pushed robots and sensors are told about it, and where each pushed thing ended
+/// up is added to `pushed`. Returns false if the cell can't be cleared.
+fn clear_path(
+    board: &mut Board,
+    robots: &mut Vec<Robot>,
+    global_robot: &mut Robot,
+    pos: &Coordinate<u16>,
+    dir: (i8, i8),
+    update_done: &mut [bool],
+    pushed: &mut Vec<(Coordinate<u16>, (i8, i8))>,
+) -> bool {
+    let mut chain = vec![];
+    let mut current = *pos;
+    loop {
+        let thing = board.thing_at(&current).unwrap();
+        if thing.is_pushable() {
+            chain.push(current);
+            current = match adjust_coordinate_diff(current, board, dir.0 as i16, dir.1 as i16) {
+                Some(next) => next,
+                None => return false,
+            };
+        } else if !chain.is_empty() && is_crushable(thing) {
+            board.remove_thing_at(&current).unwrap();
+            break;
+        } else if thing.is_solid() {
+            return false;
+        } else {
+            break;
+        }
+    }
+
+    for old_pos in chain.iter().rev() {
+        if move_level(board, robots, old_pos, dir.0, dir.1, update_done).is_err() {
+            return false;
+        }
+        let new_pos = adjust_coordinate_diff(*old_pos, board, dir.0 as i16, dir.1 as i16).unwrap();
+        pushed.push((new_pos, dir));
+        let (_id, _color, param) = *board.level_at(&new_pos).unwrap();
+        match board.thing_at(&new_pos).unwrap() {
+            Thing::RobotPushable => {
+                let mut robots = Robots::new(robots, global_robot);
+                send_robot_to_label(robots.get_mut(RobotId::from(param)), BuiltInLabel::Pushed);
+            }
+            Thing::Sensor => send_sensor_label(board, robots, global_robot, param, "sensorpushed"),
+            _ => (),
+        }
+    }
+    true
+}
+
+/// Advance the timer of every open door on the board, swinging doors shut once
+/// it runs out. A door whose frame is occupied stays open until it is clear.
+fn update_open_doors(
+    board: &mut Board,
+    robots: &mut Vec<Robot>,
+    update_done: &mut [bool],
+) {
+    let mut doors = vec![];
+    for y in 0..board.height {
+        for x in 0..board.width {
+            let pos = Coordinate(x as u16, y as u16);
+            if board.thing_at(&pos).unwrap() == Thing::OpenDoor {
+                doors.push(pos);
+            }
+        }
+    }
+
+    for pos in doors {
+        let param = board.level_at(&pos).unwrap().2;
+        if param & DOOR_TIMER_MASK != DOOR_TIMER_MASK {
+            board.level_at_mut(&pos).unwrap().2 = param + 8;
+            continue;
+        }
+
+        let movement = DOOR_FIRST_MOVEMENT[(param & 7) as usize];
+        let (xdiff, ydiff) = (-movement.0, -movement.1);
+        let frame_pos = match adjust_coordinate_diff(pos, board, xdiff as i16, ydiff as i16) {
+            Some(frame_pos) => frame_pos,
+            None => continue,
+        };
+        let blocker = board.thing_at(&frame_pos).unwrap();
+        if blocker.is_solid() || blocker.is_pushable() {
+            continue;
+        }
+
+        {
+            let (ref mut id, _, ref mut param) = board.level_at_mut(&pos).unwrap();
+            *id = Thing::Door.to_u8().unwrap();
+            *param = *param & 7;
+        }
+        if let Err(e) = move_level(board, robots, &pos, xdiff, ydiff, update_done) {
+            warn!("failed to close door at {:?}: {:?}", pos, e);
+        }
+    }
+}
+
+/// The direction a water current carries things standing in it.
+fn current_direction(thing: Thing) -> Option<(i8, i8)> {
+    match thing {
+        Thing::NWater => Some(NORTH),
+        Thing::SWater => Some(SOUTH),
+        Thing::EWater => Some(EAST),
+        Thing::WWater => Some(WEST),
+        _ => None,
+    }
+}
+
+/// Move the player one step in direction `dir`, pushing anything in the way.
+/// Returns false if the player couldn't move.
+fn move_player(
+    board: &mut Board,
+    robots: &mut Vec<Robot>,
+    global_robot: &mut Robot,
+    dir: (i8, i8),
+    update_done: &mut [bool],
+    pushed: &mut Vec<(Coordinate<u16>, (i8, i8))>,
+) -> bool {
+    let player_pos = board.player_pos;
+    let new_player_pos = match adjust_coordinate_diff(player_pos, board, dir.0 as i16, dir.1 as i16) {
+        Some(pos) => pos,
+        None => return false,
+    };
+    if !clear_path(board, robots, global_robot, &new_player_pos, dir, update_done, pushed) {
+        return false;
+    }
+    if move_level(board, robots, &player_pos, dir.0, dir.1, update_done).is_err() {
+        return false;
+    }
+    board.player_pos = new_player_pos;
+    true
+}
+
+/// Let the terrain under the player move them: water currents carry the player
+/// along, and ice keeps them sliding in the direction they last moved.
+fn apply_player_terrain(
+    board: &mut Board,
+    robots: &mut Vec<Robot>,
+    global_robot: &mut Robot,
+    status: &mut PlayerStatus,
+    orig_player_pos: Coordinate<u16>,
+    update_done: &mut [bool],
+    pushed: &mut Vec<(Coordinate<u16>, (i8, i8))>,
+) {
+    let player_pos = board.player_pos;
+    let under_player = board.under_thing_at(&player_pos).unwrap();
+    if player_pos != orig_player_pos {
+        status.sliding = if under_player == Thing::Ice {
+            Some((
+                (player_pos.0 as i16 - orig_player_pos.0 as i16).signum() as i8,
+                (player_pos.1 as i16 - orig_player_pos.1 as i16).signum() as i8,
+            ))
+        } else {
+            None
+        };
+        return;
+    }
+
+    let dir = if under_player == Thing::Ice {
+        status.sliding
+    } else {
+        status.sliding = None;
+        current_direction(under_player)
+    };
+    if let Some(dir) = dir {
+        if !move_player(board, robots, global_robot, dir, update_done, pushed) {
+            status.sliding = None;
+        }
+    }
+}
+
+/// Carry every pushable thing sitting in a water current one step downstream.
+fn update_currents(
+    board: &mut Board,
+    robots: &mut Vec<Robot>,
+    update_done: &mut [bool],
+) {
+    let mut carried = vec![];
+    for y in 0..board.height {
+        for x in 0..board.width {
+            let pos = Coordinate(x as u16, y as u16);
+            if !board.thing_at(&pos).unwrap().is_pushable() {
+                continue;
+            }
+            if let Some(dir) = current_direction(board.under_thing_at(&pos).unwrap()) {
+                carried.push((pos, dir));
+            }
+        }
+    }
+
+    for (pos, dir) in carried {
+        let dest = match adjust_coordinate_diff(pos, board, dir.0 as i16, dir.1 as i16) {
+            Some(dest) => dest,
+            None => continue,
+        };
+        let blocker = board.thing_at(&dest).unwrap();
+        if blocker.is_solid() || blocker.is_pushable() {
+            continue;
+        }
+        if let Err(e) = move_level(board, robots, &pos, dir.0, dir.1, update_done) {
+            warn!("failed to move {:?} along current: {:?}", pos, e);
+        }
+    }
+}
+
+/// Keep things the player pushed across ice sliding until they leave the ice or
+/// run into something.
+fn update_sliding_objects(
+    board: &mut Board,
+    robots: &mut Vec<Robot>,
+    sliding: &mut Vec<(Coordinate<u16>, (i8, i8))>,
+    update_done: &mut [bool],
+) {
+    let mut still_sliding = vec![];
+    for (pos, dir) in sliding.drain(..) {
+        if !board.thing_at(&pos).unwrap().is_pushable() || board.under_thing_at(&pos).unwrap() != Thing::Ice {
+            continue;
+        }
+        let dest = match adjust_coordinate_diff(pos, board, dir.0 as i16, dir.1 as i16) {
+            Some(dest) => dest,
+            None => continue,
+        };
+        let blocker = board.thing_at(&dest).unwrap();
+        if blocker.is_solid() || blocker.is_pushable() {
+            continue;
+        }
+        match move_level(board, robots, &pos, dir.0, dir.1, update_done) {
+            Ok(_) => still_sliding.push((dest, dir)),
+            Err(e) => warn!("failed to slide {:?} across ice: {:?}", pos, e),
+        }
+    }
+    *sliding = still_sliding;
+}
+
+/// The cells surrounding a spinning gear, in clockwise order.
+const ROTATION_RING: &[(i16, i16)] = &[
+    (-1, -1), (0, -1), (1, -1), (1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0),
+];
+
+/// Turn the cells around every clockwise and counter-clockwise gear on the board.
+fn update_rotators(
+    board: &mut Board,
+    robots: &mut Vec<Robot>,
+    update_done: &mut [bool],
+) {
+    let mut rotators = vec![];
+    for y in 0..board.height {
+        for x in 0..board.width {
+            let pos = Coordinate(x as u16, y as u16);
+            match board.thing_at(&pos).unwrap() {
+                Thing::CwRotate => rotators.push((pos, true)),
+                Thing::CcwRotate => rotators.push((pos, false)),
+                _ => (),
+            }
+        }
+    }
+
+    for (pos, clockwise) in rotators {
+        rotate_around(board, robots, pos, clockwise, update_done);
+    }
+}
+
+/// Shift the player and pushable things around `center` by one cell. Nothing turns
+/// if the ring contains an immovable solid or has no free cell to rotate into.
+fn rotate_around(
+    board: &mut Board,
+    robots: &mut Vec<Robot>,
+    center: Coordinate<u16>,
+    clockwise: bool,
+    update_done: &mut [bool],
+) {
+    let mut ring = vec![];
+    for &(xdiff, ydiff) in ROTATION_RING {
+        match adjust_coordinate_diff(center, board, xdiff, ydiff) {
+            Some(pos) => ring.push(pos),
+            None => return,
+        }
+    }
+    if !clockwise {
+        ring.reverse();
+    }
+
+    let is_movable = |thing: Thing| thing.is_pushable() || thing == Thing::Player;
+    let mut free = None;
+    for (i, pos) in ring.iter().enumerate() {
+        let thing = board.thing_at(pos).unwrap();
+        if is_movable(thing) {
+            continue;
+        }
+        if thing.is_solid() {
+            return;
+        }
+        free = Some(i);
+    }
+    let free = match free {
+        Some(free) => free,
+        None => return,
+    };
+
+    // Work backwards from the free cell so every move has an empty destination.
+    let len = ring.len();
+    for step in 1..len {
+        let from = ring[(free + len - step) % len];
+        let to = ring[(free + len - step + 1) % len];
+        if !is_movable(board.thing_at(&from).unwrap()) {
+            continue;
+        }
+        if move_level_to(board, robots, &from, &to, update_done).is_err() {
+            return;
+        }
+        if from == board.player_pos {
+            board.player_pos = to;
+        }
+    }
+}
+
+fn is_whirlpool(thing: Thing) -> bool {
+    match thing {
+        Thing::Whirlpool1 | Thing::Whirlpool2 | Thing::Whirlpool3 | Thing::Whirlpool4 => true,
+        _ => false,
+    }
+}
+
+/// Locate where a player arriving through `entrance` should appear on `board`.
+/// An entrance of the same kind and color is preferred, followed by one of the same
+/// kind in any color. Whirlpools animate, so any whirlpool stage is the same kind.
+fn find_entrance(board: &Board, entrance: Thing, color: u8) -> Option<Coordinate<u16>> {
+    let same_kind = |id: u8| match Thing::from_u8(id) {
+        Some(thing) => thing == entrance || (is_whirlpool(thing) && is_whirlpool(entrance)),
+        None => false,
+    };
+
+    let mut fallback = None;
+    for y in 0..board.height {
+        for x in 0..board.width {
+            let pos = Coordinate(x as u16, y as u16);
+            let level = *board.level_at(&pos).unwrap();
+            let under = *board.under_at(&pos).unwrap();
+            for &(id, level_color, _) in &[level, under] {
+                if !same_kind(id) {
+                    continue;
+                }
+                if level_color == color {
+                    return Some(pos);
+                }
+                if fallback.is_none() {
+                    fallback = Some(pos);
+                }
+            }
+        }
+    }
+    fallback
+}
+
+/// Transports carry the player in the direction they entered, out the far side of
+/// the next transport along that line which accepts travel in that direction.
+fn transport_destination(board: &Board, start: Coordinate<u16>, dir: (i8, i8)) -> Option<Coordinate<u16>> {
+    const TRANSPORT_ANY_DIRECTION: u8 = 4;
+    let accepts = |param: u8| {
+        let allowed = match param & 7 {
+            0 => NORTH,
+            1 => SOUTH,
+            2 => EAST,
+            3 => WEST,
+            TRANSPORT_ANY_DIRECTION => return true,
+            _ => return false,
+        };
+        allowed == dir
+    };
+
+    if !accepts(board.level_at(&start).unwrap().2) {
+        return None;
+    }
+
+    let mut pos = start;
+    loop {
+        pos = adjust_coordinate_diff(pos, board, dir.0 as i16, dir.1 as i16)?;
+        let (id, _, param) = *board.level_at(&pos).unwrap();
+        if Thing::from_u8(id) != Some(Thing::Transport) || !accepts(param) {
+            continue;
+        }
+        let dest = adjust_coordinate_diff(pos, board, dir.0 as i16, dir.1 as i16)?;
+        let thing = board.thing_at(&dest).unwrap();
+        if !thing.is_solid() && !thing.is_pushable() {
+            return Some(dest);
+        }
+    }
+}
+
+/// Floors and other cells that a bullet can be spawned over without destroying
+/// anything.
+fn can_hold_bullet(thing: Thing) -> bool {
+    match thing {
+        Thing::Space | Thing::Fake | Thing::Carpet | Thing::Floor | Thing::Tiles |
+        Thing::CustomFloor | Thing::Web | Thing::ThickWeb | Thing::Ice => true,
+        _ => false,
+    }
+}
+
+/// Apply a player shot to the thing at `pos`, which is directly next to the
+/// player and can't have a bullet spawned over it. Anything that doesn't react
+/// to being shot absorbs the shot.
+fn shoot_thing(
+    board: &mut Board,
+    robots: &mut Vec<Robot>,
+    global_robot: &mut Robot,
+    pos: &Coordinate<u16>,
+) {
+    let (_id, _color, param) = *board.level_at(pos).unwrap();
+    let thing = board.thing_at(pos).unwrap();
+    match thing {
+        Thing::Robot | Thing::RobotPushable => {
+            let mut robots = Robots::new(robots, global_robot);
+            let robot = robots.get_mut(RobotId::from(param));
+            send_robot_to_label(robot, BuiltInLabel::Shot);
+        }
+
+        Thing::Breakaway | Thing::CustomBreak => {
+            board.remove_thing_at(pos).unwrap();
+        }
+
+        _ if is_enemy(thing) => {
+            board.remove_thing_at(pos).unwrap();
+        }
+
+        _ => debug!("shot absorbed by {:?} at {:?}", thing, pos),
+    }
+}
+
 pub(crate) fn tick_game_loop(
     world: &mut World,
-    audio: &dyn AudioEngine,
-    world_path: &Path,
+    audio: &MusicCallback,
     input_state: &InputState,
     counters: &mut Counters,
     boards: &[ByteString],
     board_id: &mut usize,
     accept_player_input: &mut bool,
+    mut player_status: Option<&mut PlayerStatus>,
+    mut history: Option<&mut MessageHistory>,
 ) -> Option<StateChange> {
     let num_boards = world.boards.len();
     let (ref mut board, ref mut robots) = world.boards[*board_id];
     let orig_player_pos = board.player_pos;
 
     let key = convert_input(input_state);
+    let mut pushed = vec![];
     let result = process_input(
         board,
         robots,
+        &mut world.global_robot,
         &input_state,
         &mut world.state,
         accept_player_input,
+        &mut pushed,
     );
     match result {
         Some(InputResult::ExitBoard(dir)) => {
@@ -547,10 +1544,21 @@ pub(crate) fn tick_game_loop(
         }
 
         Some(InputResult::Transport(id, color, dest_board_id)) => {
-            let (ref mut dest_board, ref mut robots) = &mut world.boards[dest_board_id as usize];
-            let coord = dest_board.find(id, color).unwrap_or(dest_board.player_pos);
-            *board_id = dest_board_id as usize;
-            enter_board(&mut world.state, audio, dest_board, coord, robots, &mut world.global_robot, LabelAction::RunJustEntered, true);
+            let entrance = Thing::from_u8(id).unwrap();
+            if dest_board_id as usize >= num_boards {
+                warn!("{:?} at {:?} leads to missing board {}", entrance, board.player_pos, dest_board_id);
+                board.set_message_line(
+                    format!("Error: this {:?} leads to board {}, which does not exist.", entrance, dest_board_id)
+                        .as_str()
+                        .into()
+                );
+            } else {
+                let (ref mut dest_board, ref mut robots) = &mut world.boards[dest_board_id as usize];
+                let coord = find_entrance(dest_board, entrance, color).unwrap_or(dest_board.player_pos);
+                *board_id = dest_board_id as usize;
+                audio.play_sfx(SFX_TRANSPORT, BUILT_IN_PRIORITY);
+                enter_board(&mut world.state, audio, dest_board, coord, robots, &mut world.global_robot, LabelAction::RunJustEntered, true);
+            }
         }
 
         Some(InputResult::Collide(pos)) => {
@@ -581,6 +1589,10 @@ pub(crate) fn tick_game_loop(
                     } else {
                         MessageBoxSource::Sign
                     };
+                    if let Some(ref mut history) = history {
+                        let text = text.split(|&c| c == b'\n').map(|s| s.to_owned().into()).collect();
+                        history.record(&board.title, &format!("{:?}", thing), text);
+                    }
                     return Some(StateChange::Push(Box::new(
                         MessageBoxState::new("Scroll".into(), lines, source)
                     )));
@@ -592,9 +1604,11 @@ pub(crate) fn tick_game_loop(
                         if world.state.take_key(color & 0x0F).is_ok() {
                             board.level_at_mut(&pos).unwrap().2 = 0;
                             board.set_message_line("You unlock and open the gate.".into());
+                            audio.play_sfx(SFX_UNLOCK, BUILT_IN_PRIORITY);
                             unlocked = true;
                         } else {
                             board.set_message_line("The gate is locked!".into());
+                            audio.play_sfx(SFX_LOCKED, BUILT_IN_PRIORITY);
                         }
                     }
                     if unlocked {
@@ -605,32 +1619,79 @@ pub(crate) fn tick_game_loop(
                 }
 
                 Thing::Door => {
-                    const DOOR_FIRST_MOVEMENT: &[(i8, i8)] = &[
-                        NORTH, WEST, NORTH, EAST, SOUTH, WEST, SOUTH, EAST,
-                    ];
                     let (orientation, dir, status) = door_from_param(param);
                     let mut unlocked = status == DoorStatus::Unlocked;
                     if !unlocked {
                         if world.state.take_key(color & 0x0F).is_ok() {
                             board.level_at_mut(&pos).unwrap().2 = param_from_door(orientation, dir, DoorStatus::Unlocked);
                             board.set_message_line("You unlock and open the door.".into());
+                            audio.play_sfx(SFX_UNLOCK, BUILT_IN_PRIORITY);
                             unlocked = true;
 
                         } else {
                             board.set_message_line("The door is locked!".into());
+                            audio.play_sfx(SFX_LOCKED, BUILT_IN_PRIORITY);
                         }
                     }
 
                     if unlocked {
-                        {
-                            let (ref mut id, _, ref mut param) = board.level_at_mut(&pos).unwrap();
-                            *id = Thing::OpenDoor.to_u8().unwrap();
-                            *param = *param & 7;
-                        }
                         let movement = DOOR_FIRST_MOVEMENT[(param & 7) as usize];
-                        // FIXME: support pushing
-                        // FIXME: check for blocked, act appropriately.
-                        move_level(board, robots, &pos, movement.0, movement.1, &mut *world.state.update_done).unwrap();
+                        let can_swing = match adjust_coordinate_diff(pos, board, movement.0 as i16, movement.1 as i16) {
+                            Some(swing_pos) => clear_path(
+                                board,
+                                robots,
+                                &mut world.global_robot,
+                                &swing_pos,
+                                movement,
+                                &mut *world.state.update_done,
+                                &mut pushed,
+                            ),
+                            None => false,
+                        };
+
+                        if can_swing {
+                            {
+                                let (ref mut id, _, ref mut param) = board.level_at_mut(&pos).unwrap();
+                                *id = Thing::OpenDoor.to_u8().unwrap();
+                                *param = *param & 7;
+                            }
+                            audio.play_sfx(SFX_OPEN_DOOR, BUILT_IN_PRIORITY);
+                            if let Err(e) = move_level(board, robots, &pos, movement.0, movement.1, &mut *world.state.update_done) {
+                                warn!("failed to open door at {:?}: {:?}", pos, e);
+                            }
+                        } else {
+                            board.set_message_line("The door is blocked from opening!".into());
+                        }
+                    }
+                }
+
+                Thing::Transport => {
+                    let player_pos = board.player_pos;
+                    let dir = (
+                        (pos.0 as i16 - player_pos.0 as i16) as i8,
+                        (pos.1 as i16 - player_pos.1 as i16) as i8,
+                    );
+                    if let Some(dest) = transport_destination(board, pos, dir) {
+                        match move_level_to(board, robots, &player_pos, &dest, &mut *world.state.update_done) {
+                            Ok(_) => board.player_pos = dest,
+                            Err(e) => warn!("failed to transport player to {:?}: {:?}", dest, e),
+                        }
+                    }
+                }
+
+                Thing::Potion | Thing::Ring => {
+                    board.remove_thing_at(&pos).unwrap();
+                    if let Some(ref mut status) = player_status {
+                        apply_potion(world, *board_id, status, param);
+                    }
+                }
+
+                _ if damage_from(thing).is_some() => {
+                    if thing == Thing::Bullet {
+                        board.remove_thing_at(&pos).unwrap();
+                    }
+                    if let Some(ref mut status) = player_status {
+                        hurt_player(world, *board_id, status, damage_from(thing).unwrap());
                     }
                 }
 
@@ -650,15 +1711,19 @@ pub(crate) fn tick_game_loop(
         }
 
         Some(InputResult::Shoot(dir)) => {
-            // TODO: check world.state.ammo
-            let board = &mut world.boards[*board_id].0;
-            let adjusted = adjust_coordinate(board.player_pos, board, dir);
-            if let Some(ref bullet_pos) = adjusted {
-                // FIXME: shoot blocking object at initial position instead of overwriting
-                if !board.thing_at(bullet_pos).unwrap().is_solid() {
+            let (ref mut board, ref mut robots) = world.boards[*board_id];
+            if world.state.ammo == 0 {
+                board.set_message_line("You are out of ammo!".into());
+                audio.play_sfx(SFX_OUT_OF_AMMO, BUILT_IN_PRIORITY);
+            } else if let Some(bullet_pos) = adjust_coordinate(board.player_pos, board, dir) {
+                world.state.ammo -= 1;
+                audio.play_sfx(SFX_SHOOT, BUILT_IN_PRIORITY);
+                if !can_hold_bullet(board.thing_at(&bullet_pos).unwrap()) {
+                    shoot_thing(board, robots, &mut world.global_robot, &bullet_pos);
+                } else {
                     put_at(
                         board,
-                        bullet_pos,
+                        &bullet_pos,
                         0x07,
                         Thing::Bullet,
                         bullet_param(BulletType::Player, dir),
@@ -671,25 +1736,109 @@ pub(crate) fn tick_game_loop(
         None => (),
     }
 
-    let (ref mut board, _) = world.boards[*board_id];
+    let (ref mut board, ref mut robots) = world.boards[*board_id];
+    if let Some(ref mut status) = player_status {
+        apply_player_terrain(
+            board,
+            robots,
+            &mut world.global_robot,
+            status,
+            orig_player_pos,
+            &mut *world.state.update_done,
+            &mut pushed,
+        );
+        if status.effects.wind > 0 {
+            let dir = [NORTH, SOUTH, EAST, WEST][rand::thread_rng().gen_range(0, 4)];
+            move_player(board, robots, &mut world.global_robot, dir, &mut *world.state.update_done, &mut pushed);
+        }
+        // Whatever was pushed this cycle starts sliding on the next one.
+        update_sliding_objects(board, robots, &mut status.sliding_objects, &mut *world.state.update_done);
+        status.sliding_objects.extend(
+            pushed.into_iter().filter(|&(pos, _)| board.under_thing_at(&pos).unwrap() == Thing::Ice)
+        );
+    }
+    update_currents(board, robots, &mut *world.state.update_done);
+    update_rotators(board, robots, &mut *world.state.update_done);
+
     if board.player_pos != orig_player_pos &&
         !world.state.scroll_locked
     {
         reset_view(board);
     }
 
-    let change = run_board_update(
-        world,
-        audio,
-        world_path,
-        counters,
-        boards,
-        board_id,
-        key,
-    );
+    update_open_doors(board, robots, &mut *world.state.update_done);
+
+    if let Some(ref mut status) = player_status {
+        let under_player = board.under_thing_at(&board.player_pos).unwrap();
+        let burning = under_player == Thing::Lava || under_player == Thing::Fire;
+        if burning && status.effects.firewalking == 0 {
+            hurt_player(world, *board_id, status, damage_from(under_player).unwrap());
+        }
+    }
+
+    // Frozen time stops the board entirely; slowed time only updates it every other cycle.
+    let (time_frozen, time_slowed) = match player_status {
+        Some(ref status) => (status.effects.freeze_time > 0, status.effects.slow_time % 2 == 1),
+        None => (false, false),
+    };
+    let approach = match player_status {
+        Some(ref mut status) if !time_frozen && !time_slowed => {
+            hurt_by_aimed_bullets(world, *board_id, status);
+            let board = &world.boards[*board_id].0;
+            Some((*board_id, board.player_pos, enemies_in_line(board)))
+        }
+        _ => None,
+    };
+    let change = if time_frozen || time_slowed {
+        None
+    } else {
+        // The launcher can switch to another world, so this can't be fixed up front.
+        let world_path = audio.world_path();
+        run_board_update(
+            world,
+            audio,
+            &world_path,
+            counters,
+            boards,
+            board_id,
+            key,
+        )
+    };
+
+    if let Some(status) = player_status {
+        if let Some((old_board_id, old_player_pos, in_line)) = approach {
+            if old_board_id == *board_id && old_player_pos == world.boards[*board_id].0.player_pos {
+                hurt_by_enemies(world, *board_id, status, &in_line);
+            }
+        }
+        status.tick(world, *board_id);
+    }
+
+    if let Some(ref mut history) = history {
+        let board = &world.boards[*board_id].0;
+        history.record_message_line(&board.title, &board.message_line);
+    }
 
     match change {
         Some(ExternalStateChange::MessageBox(lines, title, rid)) => {
+            if let Some(history) = history {
+                let (ref board, ref mut robots) = world.boards[*board_id];
+                let source = match rid {
+                    Some(rid) => {
+                        let mut robots = Robots::new(robots, &mut world.global_robot);
+                        format!("Robot {}", String::from_utf8_lossy(&robots.get_mut(rid).name))
+                    }
+                    None => "Robot".to_owned(),
+                };
+                let text = lines
+                    .iter()
+                    .map(|line| match *line {
+                        MessageBoxLine::Text(ref text, _) => text.clone(),
+                        MessageBoxLine::Option { ref text, .. } => text.clone(),
+                    })
+                    .collect();
+                history.record(&board.title, &source, text);
+            }
             Some(StateChange::Push(Box::new(
                 MessageBoxState::new(title, lines, MessageBoxSource::Robot(rid))
             )))
@@ -721,12 +1870,36 @@ impl MessageBoxState {
         }
     }
 
+    /// Start with the last line selected, so the most recent text is on screen.
+    fn scrolled_to_end(mut self) -> MessageBoxState {
+        self.pos = self.lines.len().saturating_sub(1);
+        self
+    }
+
     fn pop_state(&self) -> Option<PoppedData> {
         match self.source {
             MessageBoxSource::Scroll(pos) => Some(PoppedData::Scroll(pos)),
             _ => None,
         }
     }
+
+    /// Select the next option after the current line whose text starts with
+    /// `letter`, wrapping around to the top.
+    fn jump_to_option(&mut self, letter: u8) {
+        let len = self.lines.len();
+        let next = (1..len + 1)
+            .map(|offset| (self.pos + offset) % len)
+            .find(|&i| match self.lines[i] {
+                MessageBoxLine::Option { ref text, .. } => text
+                    .iter()
+                    .find(|c| !c.is_ascii_whitespace())
+                    .map_or(false, |c| c.eq_ignore_ascii_case(&letter)),
+                _ => false,
+            });
+        if let Some(next) = next {
+            self.pos = next;
+        }
+    }
 }
 
 impl GameState for MessageBoxState {
@@ -741,25 +1914,16 @@ impl GameState for MessageBoxState {
         event: Event,
         _input_state: &mut InputState,
     ) -> Option<StateChange> {
+        if navigate_list(&event, &mut self.pos, self.lines.len()) {
+            return None;
+        }
         match event {
             Event::KeyDown {keycode: Some(Keycode::Escape), ..} =>
                 return Some(StateChange::PopCurrent(self.pop_state())),
 
-            Event::KeyDown {keycode: Some(Keycode::Up), ..} => {
-                if self.pos > 0 {
-                    self.pos -= 1;
-                }
-            }
-
-            Event::KeyDown {keycode: Some(Keycode::Down), ..} => {
-                if self.pos < self.lines.len() - 1 {
-                    self.pos += 1;
-                }
-            }
-
             Event::KeyDown {keycode: Some(Keycode::Return), ..} => {
                 if let MessageBoxSource::Robot(Some(rid)) = self.source {
-                    if let MessageBoxLine::Option { ref label, .. } = self.lines[self.pos] {
+                    if let Some(&MessageBoxLine::Option { ref label, .. }) = self.lines.get(self.pos) {
                         return Some(StateChange::PopCurrent(Some(
                             PoppedData::MessageBox(rid, label.clone())
                         )));
@@ -768,6 +1932,13 @@ impl GameState for MessageBoxState {
                 return Some(StateChange::PopCurrent(self.pop_state()));
             }
 
+            Event::KeyDown {keycode: Some(keycode), ..} => {
+                let key = keycode as i32;
+                if key >= Keycode::A as i32 && key <= Keycode::Z as i32 {
+                    self.jump_to_option(key as u8);
+                }
+            }
+
             _ => (),
         }
 
@@ -777,7 +1948,6 @@ impl GameState for MessageBoxState {
     fn tick(
         &mut self,
         _world: &mut World,
-        _world_path: &Path,
         _input_state: &InputState,
         _counters: &mut Counters,
         _boards: &[ByteString],
@@ -0,0 +1,68 @@
+use libmzx::{ByteString, MessageBoxLine, MessageBoxLineType};
+use std::collections::VecDeque;
+
+/// Older messages are dropped once this many have been recorded.
+const MAX_ENTRIES: usize = 200;
+
+struct HistoryEntry {
+    board_title: String,
+    source: String,
+    text: Vec<ByteString>,
+}
+
+/// Everything shown to the player in message boxes and on the message line
+/// during a game, so clues that were dismissed or flashed by can be read again.
+#[derive(Default)]
+pub(crate) struct MessageHistory {
+    entries: VecDeque<HistoryEntry>,
+    last_message_line: Option<ByteString>,
+}
+
+impl MessageHistory {
+    pub fn record(&mut self, board_title: &[u8], source: &str, text: Vec<ByteString>) {
+        if self.entries.len() == MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(HistoryEntry {
+            board_title: String::from_utf8_lossy(board_title).into_owned(),
+            source: source.to_owned(),
+            text,
+        });
+    }
+
+    /// The message line is only recorded when it changes, since it stays set for
+    /// many cycles after a robot writes it. Once it clears, the same message
+    /// showing up again is recorded again.
+    pub fn record_message_line(&mut self, board_title: &[u8], message_line: &ByteString) {
+        if message_line.is_empty() {
+            self.last_message_line = None;
+            return;
+        }
+        if self.last_message_line.as_ref() == Some(message_line) {
+            return;
+        }
+        self.last_message_line = Some(message_line.clone());
+        self.record(board_title, "Message line", vec![message_line.clone()]);
+    }
+
+    /// The whole history, oldest first, formatted for a message box.
+    pub fn lines(&self) -> Vec<MessageBoxLine> {
+        if self.entries.is_empty() {
+            return vec![MessageBoxLine::Text("No messages yet.".into(), MessageBoxLineType::Plain)];
+        }
+        let mut lines = vec![];
+        for entry in &self.entries {
+            if !lines.is_empty() {
+                lines.push(MessageBoxLine::Text("".into(), MessageBoxLineType::Plain));
+            }
+            let header = format!("{} - {}", entry.board_title, entry.source);
+            lines.push(MessageBoxLine::Text(header.into_bytes().into(), MessageBoxLineType::Plain));
+            for text in &entry.text {
+                let mut indented = b"  ".to_vec();
+                indented.extend_from_slice(text);
+                lines.push(MessageBoxLine::Text(indented.into(), MessageBoxLineType::Plain));
+            }
+        }
+        lines
+    }
+}
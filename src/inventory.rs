@@ -0,0 +1,108 @@
+use crate::{GameState, PoppedData, StateChange, SdlRenderer};
+use crate::game::InputState;
+use libmzx::{World, Counters, ByteString, MessageBoxLine, MessageBoxLineType, draw_messagebox};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+fn status_line(label: &str, value: &dyn ToString) -> MessageBoxLine {
+    let text = format!("{:<12}{}", label, value.to_string());
+    MessageBoxLine::Text(text.into_bytes().into(), MessageBoxLineType::Plain)
+}
+
+/// The status screen listing the player's inventory, any status counters the
+/// world asks to display, and active potion effects.
+pub(crate) struct InventoryState {
+    effects: Vec<(&'static str, u16)>,
+    lines: Vec<MessageBoxLine>,
+}
+
+impl InventoryState {
+    pub fn new(effects: Vec<(&'static str, u16)>) -> InventoryState {
+        InventoryState {
+            effects,
+            lines: vec![],
+        }
+    }
+
+    fn build_lines(&self, world: &World, counters: &Counters) -> Vec<MessageBoxLine> {
+        let state = &world.state;
+        let keys = state.keys.iter().filter(|k| k.is_some()).count();
+        let mut lines = vec![
+            status_line("Health", &state.health),
+            status_line("Lives", &state.lives),
+            status_line("Ammo", &state.ammo),
+            status_line("Gems", &state.gems),
+            status_line("Coins", &state.coins),
+            status_line("Score", &state.score),
+            status_line("Keys", &keys),
+        ];
+
+        let shown_counters: Vec<_> = world.status_counters
+            .iter()
+            .filter(|name| !name.is_empty())
+            .collect();
+        if !shown_counters.is_empty() {
+            lines.push(MessageBoxLine::Text(ByteString::from(""), MessageBoxLineType::Plain));
+            for name in shown_counters {
+                let value = counters.get(name).unwrap_or(0);
+                lines.push(status_line(&String::from_utf8_lossy(name), &value));
+            }
+        }
+
+        if !self.effects.is_empty() {
+            lines.push(MessageBoxLine::Text(ByteString::from(""), MessageBoxLineType::Plain));
+            for &(name, cycles) in &self.effects {
+                lines.push(status_line(name, &cycles));
+            }
+        }
+        lines
+    }
+}
+
+impl GameState for InventoryState {
+    fn init(&mut self, _world: &mut World, _board_id: &mut usize) {
+    }
+
+    fn popped(&mut self, _world: &mut World, _board_id: usize, _data: PoppedData) {
+    }
+
+    fn input(
+        &mut self,
+        event: Event,
+        _input_state: &mut InputState,
+    ) -> Option<StateChange> {
+        match event {
+            Event::KeyDown {keycode: Some(Keycode::Escape), ..} |
+            Event::KeyDown {keycode: Some(Keycode::Return), ..} =>
+                Some(StateChange::PopCurrent(None)),
+            _ => None,
+        }
+    }
+
+    fn tick(
+        &mut self,
+        world: &mut World,
+        _input_state: &InputState,
+        counters: &mut Counters,
+        _boards: &[ByteString],
+        _board_id: &mut usize,
+    ) -> Option<StateChange> {
+        self.lines = self.build_lines(world, counters);
+        None
+    }
+
+    fn render(
+        &mut self,
+        world: &World,
+        _board_id: usize,
+        canvas: &mut Canvas<Window>,
+    ) {
+        if self.lines.is_empty() {
+            return;
+        }
+        let mut renderer = SdlRenderer { canvas };
+        draw_messagebox(&world.state, &"Status".into(), &self.lines, 0, &mut renderer);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::{GameState, PoppedData, StateChange, SdlRenderer, read_world};
+use crate::audio::MusicCallback;
+use crate::game::{InputState, TitleState};
+use crate::menu::navigate_list;
+use crate::settings::Settings;
+use libmzx::{World, Counters, ByteString, MessageBoxLine, MessageBoxLineType, draw_messagebox};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::Color;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Length of the title at the start of every world file.
+const WORLD_TITLE_LEN: usize = 25;
+
+pub(crate) struct LauncherEntry {
+    pub path: PathBuf,
+    pub title: String,
+}
+
+fn is_world_file(path: &Path) -> bool {
+    path.extension().map_or(false, |ext| ext.to_string_lossy().eq_ignore_ascii_case("mzx"))
+}
+
+fn entry_line(title: &str, path: &Path) -> MessageBoxLine {
+    let file_name = path.file_name().unwrap().to_string_lossy();
+    let text = format!("{:<27}{}", title, file_name);
+    MessageBoxLine::Text(text.into_bytes().into(), MessageBoxLineType::Plain)
+}
+
+fn read_world_title(path: &Path) -> Option<String> {
+    let mut title = [0; WORLD_TITLE_LEN];
+    File::open(path).ok()?.read_exact(&mut title).ok()?;
+    let len = title.iter().position(|&c| c == 0).unwrap_or(WORLD_TITLE_LEN);
+    Some(String::from_utf8_lossy(&title[..len]).trim().to_owned())
+}
+
+/// Find every world in `dir` and in its immediate subdirectories, since most
+/// games are distributed in a directory of their own. Only the title is read, so
+/// a world that turns out to be broken is caught once it is picked.
+pub(crate) fn find_worlds(dir: &Path) -> Vec<LauncherEntry> {
+    let mut paths = vec![];
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Error reading {} ({})", dir.display(), e);
+            return vec![];
+        }
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path.is_dir() {
+            if let Ok(sub_entries) = fs::read_dir(&path) {
+                paths.extend(sub_entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| is_world_file(p)));
+            }
+        } else if is_world_file(&path) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let title = read_world_title(&path)?;
+            Some(LauncherEntry { path, title })
+        })
+        .collect()
+}
+
+/// Lists the worlds found on disk and starts the one that is picked.
+pub(crate) struct LauncherState {
+    music: MusicCallback,
+    settings: Settings,
+    entries: Vec<LauncherEntry>,
+    lines: Vec<MessageBoxLine>,
+    pos: usize,
+}
+
+impl LauncherState {
+    pub fn new(music: MusicCallback, settings: Settings, entries: Vec<LauncherEntry>) -> LauncherState {
+        let lines = entries.iter().map(|entry| entry_line(&entry.title, &entry.path)).collect();
+        LauncherState {
+            music,
+            settings,
+            entries,
+            lines,
+            pos: 0,
+        }
+    }
+
+    /// Start the selected world, or mark it as broken if it can't be loaded.
+    fn launch(&mut self) -> Option<StateChange> {
+        let path = &self.entries[self.pos].path;
+        match read_world(path) {
+            Ok(world) => Some(StateChange::Replace(Box::new(
+                TitleState::loaded(self.music.clone(), self.settings.clone(), path, world)
+            ))),
+            Err(e) => {
+                warn!("{}", e);
+                self.lines[self.pos] = entry_line("(can't be loaded)", path);
+                None
+            }
+        }
+    }
+}
+
+impl GameState for LauncherState {
+    fn init(&mut self, _world: &mut World, _board_id: &mut usize) {
+    }
+
+    fn popped(&mut self, _world: &mut World, _board_id: usize, _data: PoppedData) {
+    }
+
+    fn input(
+        &mut self,
+        event: Event,
+        _input_state: &mut InputState,
+    ) -> Option<StateChange> {
+        if navigate_list(&event, &mut self.pos, self.entries.len()) {
+            return None;
+        }
+        match event {
+            Event::KeyDown {keycode: Some(Keycode::Escape), ..} =>
+                return Some(StateChange::PopCurrent(None)),
+
+            Event::KeyDown {keycode: Some(Keycode::Return), ..} =>
+                return self.launch(),
+
+            // The wheel picks a world and a click starts it.
+            Event::MouseButtonDown {mouse_btn: MouseButton::Left, ..} =>
+                return self.launch(),
+
+            _ => (),
+        }
+
+        None
+    }
+
+    fn tick(
+        &mut self,
+        _world: &mut World,
+        _input_state: &InputState,
+        _counters: &mut Counters,
+        _boards: &[ByteString],
+        _board_id: &mut usize,
+    ) -> Option<StateChange> {
+        None
+    }
+
+    fn render(
+        &mut self,
+        world: &World,
+        _board_id: usize,
+        canvas: &mut Canvas<Window>,
+    ) {
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        let mut renderer = SdlRenderer { canvas };
+        draw_messagebox(&world.state, &"Choose a world".into(), &self.lines, self.pos, &mut renderer);
+    }
+}
@@ -11,6 +11,9 @@ extern crate time;
 
 use crate::audio::MusicCallback;
 use crate::game::{InputState, TitleState, PlayState, update_key_states};
+use crate::launcher::{LauncherEntry, LauncherState, find_worlds};
+use crate::recorder::AudioRecorder;
+use crate::settings::{CONFIG_FILE, Settings, SettingsData};
 use libmzx::{load_world, World, Counters, Renderer, ByteString, Coordinate};
 use sdl2::event::Event;
 use sdl2::pixels::Color;
@@ -19,13 +22,26 @@ use sdl2::video::Window;
 use std::env;
 use std::fs::File;
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::sync::mpsc::channel;
 use std::time::Duration;
 
+mod assets;
 mod audio;
 //mod board;
 mod game;
+mod history;
+mod inventory;
+mod launcher;
+mod menu;
+mod player;
+mod recorder;
+mod ring;
+mod samples;
+mod save;
+mod settings;
+mod sfx;
 //mod robot;
 
 struct SdlRenderer<'a> {
@@ -60,6 +76,9 @@ enum StateChange {
 enum PoppedData {
     MessageBox(libmzx::robot::RobotId, libmzx::ByteString),
     Scroll(Coordinate<u16>),
+    PauseMenu(menu::PauseChoice),
+    LoadSave(Option<u8>),
+    StartBoard(usize),
 }
 
 trait GameState {
@@ -85,7 +104,6 @@ trait GameState {
     fn tick(
         &mut self,
         world: &mut World,
-        world_path: &Path,
         input_state: &InputState,
         counters: &mut Counters,
         boards: &[ByteString],
@@ -128,28 +146,32 @@ fn update_state(
     }
 }
 
-fn run(world_path: &Path, starting_board: Option<usize>, silent: bool) {
+fn read_world(world_path: &Path) -> Result<World, String> {
     let world_data = match File::open(&world_path) {
         Ok(mut file) => {
             let mut v = vec![];
             file.read_to_end(&mut v).unwrap();
             v
         }
-        Err(e) => {
-            println!("Error opening {} ({})", world_path.display(), e);
-            exit(1)
-        }
+        Err(e) => return Err(format!("Error opening {} ({})", world_path.display(), e)),
     };
 
-    let mut world = match load_world(&world_data) {
-        Ok(world) => world,
-        Err(e) => {
-            println!("Error reading {} ({:?})", world_path.display(), e);
-            exit(1)
-        }
-    };
+    load_world(&world_data)
+        .map_err(|e| format!("Error reading {} ({:?})", world_path.display(), e))
+}
 
-    let world_path = Path::new(&world_path).parent().unwrap();
+/// Start playing `world`, loaded from `world_file`, or when `launcher` holds a
+/// list of worlds, let the player choose one of them first.
+fn run(
+    mut world: World,
+    world_file: &Path,
+    launcher: Option<Vec<LauncherEntry>>,
+    starting_board: Option<usize>,
+    silent: bool,
+    settings: SettingsData,
+    record_audio: Option<PathBuf>,
+) {
+    let world_path = Path::new(&world_file).parent().unwrap();
 
     let sdl_context = sdl2::init().unwrap();
     let audio_subsystem = sdl_context.audio().unwrap();
@@ -168,24 +190,40 @@ fn run(world_path: &Path, starting_board: Option<usize>, silent: bool) {
 
     canvas.set_draw_color(Color::RGBA(255, 255, 255, 255));
 
-    let music = MusicCallback::new(&world_path, silent);
-    let _device = audio::init_sdl(&audio_subsystem, music.clone());
+    let (music, mut mixer) = MusicCallback::new(&world_path, silent);
+    let recording = record_audio.as_ref().map(|path| {
+        let (sender, receiver) = channel();
+        let (spare_sender, spare_receiver) = channel();
+        mixer.record_to(sender, spare_receiver);
+        (path, receiver, spare_sender)
+    });
+    let device = audio::init_sdl(&audio_subsystem, mixer);
+    let recorder = recording.map(|(path, receiver, spare)| {
+        match AudioRecorder::start(path, device.spec().freq as u32, receiver, spare) {
+            Ok(recorder) => recorder,
+            Err(e) => {
+                println!("Error creating {} ({})", path.display(), e);
+                exit(1)
+            }
+        }
+    });
 
     let mut events = sdl_context.event_pump().unwrap();
 
-    let game_speed: u64 = 4;
+    let settings = Settings::new(settings);
 
-    let mut states = vec![if starting_board.is_none() {
-        Box::new(TitleState(music.clone())) as Box<dyn GameState>
+    let mut states = vec![if let Some(entries) = launcher {
+        Box::new(LauncherState::new(music.clone(), settings.clone(), entries)) as Box<dyn GameState>
+    } else if starting_board.is_none() {
+        Box::new(TitleState::new(music.clone(), settings.clone(), world_file)) as Box<dyn GameState>
     } else {
-        Box::new(PlayState::new(music.clone(), starting_board)) as Box<PlayState>
+        Box::new(PlayState::new(music.clone(), settings.clone(), world_file, starting_board)) as Box<PlayState>
     }];
 
     let mut board_id = 0;
     states[0].init(&mut world, &mut board_id);
 
     let mut counters = Counters::new();
-    let boards: Vec<_> = world.boards.iter().map(|(b, _)| b.title.clone()).collect();
 
     let mut last_input_state = InputState::default();
     'mainloop: loop {
@@ -212,10 +250,16 @@ fn run(world_path: &Path, starting_board: Option<usize>, silent: bool) {
             update_state(&mut states, change, &mut world, &mut board_id);
         }
 
+        // Fades are measured in game cycles, which the settings menu can change at any time.
+        music.set_fade_length(settings.get().fade_cycles, settings.cycle_ms().max(16));
+        music.set_volumes(settings.get().volumes.scaled());
+
+        // The launcher can switch to another world, so this can't be fixed up front.
+        let boards: Vec<_> = world.boards.iter().map(|(b, _)| b.title.clone()).collect();
+
         if let Some(state) = states.last_mut() {
             let change = state.tick(
                 &mut world,
-                &world_path,
                 &input_state,
                 &mut counters,
                 &boards,
@@ -236,22 +280,98 @@ fn run(world_path: &Path, starting_board: Option<usize>, silent: bool) {
 
         let now = time::precise_time_ns();
         let elapsed_ms = (now - start) / 1_000_000;
-        let total_ticks = (16 * (game_speed - 1)).checked_sub(elapsed_ms);
+        let total_ticks = settings.cycle_ms().checked_sub(elapsed_ms);
         if let Some(diff) = total_ticks {
             ::std::thread::sleep(Duration::from_millis(diff));
         }
 
         last_input_state = input_state;
     }
+
+    // Closing the device drops the mixer, which ends the recording.
+    drop(device);
+    if let Some(recorder) = recorder {
+        recorder.finish();
+    }
+}
+
+const USAGE: &str = "Usage: cargo run [--music-volume N] [--sample-volume N] [--pc-speaker-volume N] \
+                     [--master-volume N] [--game-speed N] [--record-audio file.wav] \
+                     /path/to/world.mzx [board id]";
+
+/// Split `--name value` options, which override the config file, from the
+/// positional arguments.
+fn parse_args(
+    settings: &mut SettingsData,
+    record_audio: &mut Option<PathBuf>,
+) -> Result<Vec<String>, String> {
+    let mut args = env::args();
+    let mut positional = vec![args.next().unwrap_or_default()];
+    while let Some(arg) = args.next() {
+        if arg.starts_with("--") {
+            let name = arg[2..].replace('-', "_");
+            let value = args.next().ok_or_else(|| format!("{} needs a value", arg))?;
+            if name == "record_audio" {
+                *record_audio = Some(PathBuf::from(value));
+            } else {
+                settings.apply(&name, &value)?;
+            }
+        } else {
+            positional.push(arg);
+        }
+    }
+    Ok(positional)
 }
 
 fn main() {
     env_logger::init();
-    let args: Vec<_> = env::args().collect();
+    let mut settings = SettingsData::default();
+    settings.load_config(Path::new(CONFIG_FILE));
+    let mut record_audio = None;
+    let args = match parse_args(&mut settings, &mut record_audio) {
+        Ok(args) => args,
+        Err(e) => {
+            println!("{}", e);
+            println!("{}", USAGE);
+            exit(1)
+        }
+    };
+    let silent = env::var("SILENT").ok().map_or(false, |s| !s.is_empty());
+    settings.developer_mode = env::var("MZXPLAY_DEV").ok().map_or(false, |s| !s.is_empty());
     if args.len() < 2 {
-        println!("Usage: cargo run /path/to/world.mzx [board id]")
+        let games_dir = env::var("MZXPLAY_GAMES").unwrap_or_else(|_| ".".to_owned());
+        let mut entries = find_worlds(Path::new(&games_dir));
+        // The launcher is drawn with a world's charset and palette, so the first
+        // world that loads is needed up front. The rest are only loaded once picked.
+        let mut first_world = None;
+        while first_world.is_none() && !entries.is_empty() {
+            match read_world(&entries[0].path) {
+                Ok(world) => first_world = Some((world, entries[0].path.clone())),
+                Err(e) => {
+                    warn!("{}", e);
+                    entries.remove(0);
+                }
+            }
+        }
+        let (world, world_file) = match first_world {
+            Some(first_world) => first_world,
+            None => {
+                println!("No worlds found in {}", games_dir);
+                println!("{}", USAGE);
+                return;
+            }
+        };
+        run(world, &world_file, Some(entries), None, silent, settings, record_audio);
     } else {
-        let silent = env::var("SILENT").ok().map_or(false, |s| !s.is_empty());
-        run(Path::new(&args[1]), args.get(2).and_then(|a| a.parse().ok()), silent);
+        let world_file = Path::new(&args[1]);
+        let world = match read_world(world_file) {
+            Ok(world) => world,
+            Err(e) => {
+                println!("{}", e);
+                exit(1)
+            }
+        };
+        let starting_board = args.get(2).and_then(|a| a.parse().ok());
+        run(world, world_file, None, starting_board, silent, settings, record_audio);
     }
 }
@@ -0,0 +1,324 @@
+use crate::{GameState, PoppedData, StateChange, SdlRenderer};
+use crate::game::InputState;
+use crate::save::{NUM_SLOTS, SaveInfo, read_save_info, save_path};
+use libmzx::{World, Counters, ByteString, MessageBoxLine, MessageBoxLineType, draw_messagebox};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use std::iter;
+use std::path::Path;
+
+/// How far PageUp and PageDown move, which is about the number of lines a
+/// message box shows at once.
+const MESSAGEBOX_PAGE_LINES: i32 = 18;
+
+/// Move the selection `pos` in a list of `len` lines by `offset`, stopping at
+/// either end.
+pub(crate) fn move_selection(pos: &mut usize, len: usize, offset: i32) {
+    let last = len.saturating_sub(1) as i32;
+    *pos = (*pos as i32 + offset).max(0).min(last) as usize;
+}
+
+/// Handle the keys and mouse wheel that move the selection in every menu and
+/// message box. Returns false if `event` isn't one of them.
+pub(crate) fn navigate_list(event: &Event, pos: &mut usize, len: usize) -> bool {
+    let offset = match *event {
+        Event::KeyDown {keycode: Some(Keycode::Up), ..} => -1,
+        Event::KeyDown {keycode: Some(Keycode::Down), ..} => 1,
+        Event::KeyDown {keycode: Some(Keycode::PageUp), ..} => -MESSAGEBOX_PAGE_LINES,
+        Event::KeyDown {keycode: Some(Keycode::PageDown), ..} => MESSAGEBOX_PAGE_LINES,
+        Event::KeyDown {keycode: Some(Keycode::Home), ..} => -(len as i32),
+        Event::KeyDown {keycode: Some(Keycode::End), ..} => len as i32,
+        Event::MouseWheel {y, ..} => -y,
+        _ => return false,
+    };
+    move_selection(pos, len, offset);
+    true
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum PauseChoice {
+    Resume,
+    Save,
+    Load,
+    Settings,
+    RestartBoard,
+    QuitToTitle,
+}
+
+const PAUSE_ITEMS: &[(&str, PauseChoice)] = &[
+    ("Resume", PauseChoice::Resume),
+    ("Save game", PauseChoice::Save),
+    ("Load game", PauseChoice::Load),
+    ("Settings", PauseChoice::Settings),
+    ("Restart board", PauseChoice::RestartBoard),
+    ("Quit to title", PauseChoice::QuitToTitle),
+];
+
+/// The menu shown over a game in progress when Escape is pressed. Everything other
+/// than resuming is handed back to the game state underneath to carry out.
+pub(crate) struct PauseMenuState {
+    lines: Vec<MessageBoxLine>,
+    pos: usize,
+}
+
+impl PauseMenuState {
+    pub fn new() -> PauseMenuState {
+        PauseMenuState {
+            lines: PAUSE_ITEMS
+                .iter()
+                .map(|&(text, _)| MessageBoxLine::Text(text.into(), MessageBoxLineType::Plain))
+                .collect(),
+            pos: 0,
+        }
+    }
+}
+
+impl GameState for PauseMenuState {
+    fn init(&mut self, _world: &mut World, _board_id: &mut usize) {
+    }
+
+    fn popped(&mut self, _world: &mut World, _board_id: usize, _data: PoppedData) {
+    }
+
+    fn input(
+        &mut self,
+        event: Event,
+        _input_state: &mut InputState,
+    ) -> Option<StateChange> {
+        if navigate_list(&event, &mut self.pos, PAUSE_ITEMS.len()) {
+            return None;
+        }
+        match event {
+            Event::KeyDown {keycode: Some(Keycode::Escape), ..} =>
+                return Some(StateChange::PopCurrent(None)),
+
+            Event::KeyDown {keycode: Some(Keycode::Return), ..} => {
+                let choice = PAUSE_ITEMS[self.pos].1;
+                let data = if choice == PauseChoice::Resume {
+                    None
+                } else {
+                    Some(PoppedData::PauseMenu(choice))
+                };
+                return Some(StateChange::PopCurrent(data));
+            }
+
+            _ => (),
+        }
+
+        None
+    }
+
+    fn tick(
+        &mut self,
+        _world: &mut World,
+        _input_state: &InputState,
+        _counters: &mut Counters,
+        _boards: &[ByteString],
+        _board_id: &mut usize,
+    ) -> Option<StateChange> {
+        None
+    }
+
+    fn render(
+        &mut self,
+        world: &World,
+        _board_id: usize,
+        canvas: &mut Canvas<Window>,
+    ) {
+        let mut renderer = SdlRenderer { canvas };
+        draw_messagebox(&world.state, &"Paused".into(), &self.lines, self.pos, &mut renderer);
+    }
+}
+
+/// Lists the main save file and every quicksave slot for a world, with the board
+/// each was saved on, the time played and a thumbnail of the screen.
+pub(crate) struct LoadBrowserState {
+    slots: Vec<(Option<u8>, Option<SaveInfo>)>,
+    lines: Vec<MessageBoxLine>,
+    pos: usize,
+}
+
+impl LoadBrowserState {
+    pub fn new(world_file: &Path) -> LoadBrowserState {
+        let slots: Vec<_> = iter::once(None)
+            .chain((1..NUM_SLOTS + 1).map(Some))
+            .map(|slot| (slot, read_save_info(&save_path(world_file, slot)).ok()))
+            .collect();
+        let lines = slots
+            .iter()
+            .map(|&(slot, ref info)| {
+                let name = match slot {
+                    Some(slot) => format!("Slot {}", slot),
+                    None => "Saved game".to_owned(),
+                };
+                let text = match *info {
+                    Some(ref info) => {
+                        let secs = info.play_time.as_secs();
+                        format!(
+                            "{:<11}{:<34}{}:{:02}:{:02}",
+                            name,
+                            info.board_title,
+                            secs / 3600,
+                            secs / 60 % 60,
+                            secs % 60,
+                        )
+                    }
+                    None => format!("{:<11}(empty)", name),
+                };
+                MessageBoxLine::Text(text.into_bytes().into(), MessageBoxLineType::Plain)
+            })
+            .collect();
+        LoadBrowserState {
+            slots,
+            lines,
+            pos: 0,
+        }
+    }
+}
+
+impl GameState for LoadBrowserState {
+    fn init(&mut self, _world: &mut World, _board_id: &mut usize) {
+    }
+
+    fn popped(&mut self, _world: &mut World, _board_id: usize, _data: PoppedData) {
+    }
+
+    fn input(
+        &mut self,
+        event: Event,
+        _input_state: &mut InputState,
+    ) -> Option<StateChange> {
+        if navigate_list(&event, &mut self.pos, self.slots.len()) {
+            return None;
+        }
+        match event {
+            Event::KeyDown {keycode: Some(Keycode::Escape), ..} =>
+                return Some(StateChange::PopCurrent(None)),
+
+            Event::KeyDown {keycode: Some(Keycode::Return), ..} => {
+                let (slot, ref info) = self.slots[self.pos];
+                if info.is_some() {
+                    return Some(StateChange::PopCurrent(Some(PoppedData::LoadSave(slot))));
+                }
+            }
+
+            _ => (),
+        }
+
+        None
+    }
+
+    fn tick(
+        &mut self,
+        _world: &mut World,
+        _input_state: &InputState,
+        _counters: &mut Counters,
+        _boards: &[ByteString],
+        _board_id: &mut usize,
+    ) -> Option<StateChange> {
+        None
+    }
+
+    fn render(
+        &mut self,
+        world: &World,
+        _board_id: usize,
+        canvas: &mut Canvas<Window>,
+    ) {
+        {
+            let mut renderer = SdlRenderer { canvas: &mut *canvas };
+            draw_messagebox(&world.state, &"Load game".into(), &self.lines, self.pos, &mut renderer);
+        }
+
+        let thumbnail = match self.slots[self.pos].1 {
+            Some(ref info) => &info.thumbnail,
+            None => return,
+        };
+        const THUMBNAIL_ZOOM: u32 = 2;
+        let left = 640 - (thumbnail.width as i32 + 2) * THUMBNAIL_ZOOM as i32;
+        let top = 350 - (thumbnail.height as i32 + 2) * THUMBNAIL_ZOOM as i32;
+        for (i, &(r, g, b)) in thumbnail.pixels.iter().enumerate() {
+            let x = left + (i % thumbnail.width) as i32 * THUMBNAIL_ZOOM as i32;
+            let y = top + (i / thumbnail.width) as i32 * THUMBNAIL_ZOOM as i32;
+            canvas.set_draw_color(Color::RGB(r, g, b));
+            canvas.fill_rect(Rect::new(x, y, THUMBNAIL_ZOOM, THUMBNAIL_ZOOM)).unwrap();
+        }
+    }
+}
+
+/// Lists every board in the world so the game can be started on any of them.
+pub(crate) struct BoardPickerState {
+    lines: Vec<MessageBoxLine>,
+    pos: usize,
+}
+
+impl BoardPickerState {
+    pub fn new() -> BoardPickerState {
+        BoardPickerState {
+            lines: vec![],
+            pos: 0,
+        }
+    }
+}
+
+impl GameState for BoardPickerState {
+    fn init(&mut self, world: &mut World, _board_id: &mut usize) {
+        self.lines = world.boards
+            .iter()
+            .enumerate()
+            .map(|(id, &(ref board, _))| {
+                let text = format!("{:>3}  {}", id, String::from_utf8_lossy(&board.title));
+                MessageBoxLine::Text(text.into_bytes().into(), MessageBoxLineType::Plain)
+            })
+            .collect();
+    }
+
+    fn popped(&mut self, _world: &mut World, _board_id: usize, _data: PoppedData) {
+    }
+
+    fn input(
+        &mut self,
+        event: Event,
+        _input_state: &mut InputState,
+    ) -> Option<StateChange> {
+        if navigate_list(&event, &mut self.pos, self.lines.len()) {
+            return None;
+        }
+        match event {
+            Event::KeyDown {keycode: Some(Keycode::Escape), ..} =>
+                return Some(StateChange::PopCurrent(None)),
+
+            Event::KeyDown {keycode: Some(Keycode::Return), ..} =>
+                return Some(StateChange::PopCurrent(Some(PoppedData::StartBoard(self.pos)))),
+
+            _ => (),
+        }
+
+        None
+    }
+
+    fn tick(
+        &mut self,
+        _world: &mut World,
+        _input_state: &InputState,
+        _counters: &mut Counters,
+        _boards: &[ByteString],
+        _board_id: &mut usize,
+    ) -> Option<StateChange> {
+        None
+    }
+
+    fn render(
+        &mut self,
+        world: &World,
+        _board_id: usize,
+        canvas: &mut Canvas<Window>,
+    ) {
+        let mut renderer = SdlRenderer { canvas };
+        draw_messagebox(&world.state, &"Start at board".into(), &self.lines, self.pos, &mut renderer);
+    }
+}
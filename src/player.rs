@@ -0,0 +1,407 @@
+use libmzx::{
+    World, Board, Robot, Thing, Coordinate, CardinalDirection, BulletType, adjust_coordinate, adjust_coordinate_diff,
+    bullet_param,
+};
+use libmzx::board::{move_level_to, put_at, reset_view};
+use rand::{self, Rng};
+
+/// Number of cycles after being hurt during which further damage is ignored.
+const HURT_INVULNERABILITY_CYCLES: u8 = 8;
+
+/// Length in cycles of the invincibility potion.
+const INVINCO_CYCLES: u16 = 113;
+
+/// Length in cycles of every other timed potion effect.
+const EFFECT_CYCLES: u16 = 200;
+
+/// Explosion size used by the blast and detonate potions.
+const POTION_EXPLOSION_SIZE: u8 = 4;
+
+/// In the order bullet params number them.
+const DIRECTIONS: [CardinalDirection; 4] = [
+    CardinalDirection::North, CardinalDirection::South, CardinalDirection::East, CardinalDirection::West,
+];
+
+/// Timed effects granted by potions and rings, in cycles remaining.
+#[derive(Default)]
+pub(crate) struct Effects {
+    pub invincible: u16,
+    pub blind: u16,
+    pub firewalking: u16,
+    pub freeze_time: u16,
+    pub slow_time: u16,
+    pub wind: u16,
+}
+
+impl Effects {
+    /// Every timer, in the same order as `timers_mut`.
+    pub fn timers(&self) -> [u16; 6] {
+        [self.invincible, self.blind, self.firewalking, self.freeze_time, self.slow_time, self.wind]
+    }
+
+    /// Every timer, in a fixed order.
+    pub fn timers_mut(&mut self) -> [&mut u16; 6] {
+        [
+            &mut self.invincible,
+            &mut self.blind,
+            &mut self.firewalking,
+            &mut self.freeze_time,
+            &mut self.slow_time,
+            &mut self.wind,
+        ]
+    }
+
+    fn tick(&mut self) {
+        for timer in self.timers_mut().iter_mut() {
+            **timer = timer.saturating_sub(1);
+        }
+    }
+
+    /// The name and remaining cycles of every effect that is currently active.
+    pub fn active(&self) -> Vec<(&'static str, u16)> {
+        vec![
+            ("Invinco", self.invincible),
+            ("Blind", self.blind),
+            ("Lava walk", self.firewalking),
+            ("Freeze time", self.freeze_time),
+            ("Slow time", self.slow_time),
+            ("Wind", self.wind),
+        ].into_iter().filter(|&(_, cycles)| cycles > 0).collect()
+    }
+}
+
+/// Player state that outlives a single cycle but isn't tracked by the world itself.
+#[derive(Default)]
+pub(crate) struct PlayerStatus {
+    /// Cycles remaining before the player can be hurt again.
+    pub invulnerable: u8,
+    /// The board the player most recently entered and where they entered it.
+    pub entrance: Option<(usize, Coordinate<u16>)>,
+    /// Set once the player has lost their last life.
+    pub game_over: bool,
+    /// The direction the player keeps moving in while standing on ice.
+    pub sliding: Option<(i8, i8)>,
+    /// Things the player pushed onto ice, with where they are now and the
+    /// direction they keep sliding in.
+    pub sliding_objects: Vec<(Coordinate<u16>, (i8, i8))>,
+    /// Enemies that stepped straight at the player in the last board update, with
+    /// where they are, the direction they were heading and what their cell held.
+    pub approaching: Vec<(Coordinate<u16>, (i16, i16), (u8, u8, u8))>,
+    /// Potion and ring effects currently in force.
+    pub effects: Effects,
+}
+
+impl PlayerStatus {
+    /// Run once per cycle after the board has been updated.
+    pub fn tick(&mut self, world: &World, board_id: usize) {
+        if self.invulnerable > 0 {
+            self.invulnerable -= 1;
+        }
+        self.effects.tick();
+        if self.entrance.map_or(true, |(id, _)| id != board_id) {
+            self.entrance = Some((board_id, world.boards[board_id].0.player_pos));
+            self.sliding_objects.clear();
+            self.approaching.clear();
+        }
+    }
+}
+
+pub(crate) fn is_enemy(thing: Thing) -> bool {
+    match thing {
+        Thing::Seeker | Thing::Snake | Thing::Eye | Thing::Thief | Thing::SlimeBlob |
+        Thing::Runner | Thing::Ghost | Thing::Dragon | Thing::Fish | Thing::Shark |
+        Thing::Spider | Thing::Goblin | Thing::SpittingTiger | Thing::Bear |
+        Thing::BearCub => true,
+        _ => false,
+    }
+}
+
+/// The amount of health lost by touching the given thing, if it is harmful.
+pub(crate) fn damage_from(thing: Thing) -> Option<u32> {
+    Some(match thing {
+        Thing::Lava => 25,
+        Thing::Fire | Thing::Explosion => 10,
+        Thing::Bullet | Thing::Missile => 10,
+        Thing::Spike => 5,
+        Thing::CustomHurt => 10,
+        Thing::Thief => return None,
+        _ if is_enemy(thing) => 10,
+        _ => return None,
+    })
+}
+
+/// Reduce the player's health, taking invulnerability, the board's "restart if hurt"
+/// option and lost lives into account.
+pub(crate) fn hurt_player(
+    world: &mut World,
+    board_id: usize,
+    status: &mut PlayerStatus,
+    damage: u32,
+) {
+    if damage == 0 || status.invulnerable > 0 || status.effects.invincible > 0 || status.game_over {
+        return;
+    }
+    status.invulnerable = HURT_INVULNERABILITY_CYCLES;
+    world.state.health = world.state.health.saturating_sub(damage);
+
+    let (ref mut board, ref mut robots) = world.boards[board_id];
+    if world.state.health == 0 {
+        world.state.lives = world.state.lives.saturating_sub(1);
+        if world.state.lives == 0 {
+            status.game_over = true;
+            return;
+        }
+        world.state.health = world.starting_health as u32;
+        board.set_message_line("You have died...".into());
+        restart_player(board, robots, status, &mut *world.state.update_done);
+        return;
+    }
+
+    board.set_message_line("Ouch!".into());
+    if board.restart_if_zapped {
+        restart_player(board, robots, status, &mut *world.state.update_done);
+    }
+}
+
+fn is_player_bullet(param: u8) -> bool {
+    DIRECTIONS.iter().any(|&dir| bullet_param(BulletType::Player, dir) == param)
+}
+
+/// Bullet params keep the direction of flight in their low two bits.
+fn bullet_direction(param: u8) -> CardinalDirection {
+    DIRECTIONS[(param & 3) as usize]
+}
+
+/// The board update has no say over the player's health, so a bullet next to the
+/// player and flying at them hits them here, before the update moves it into
+/// them. The bullet is used up by the hit.
+pub(crate) fn hurt_by_aimed_bullets(world: &mut World, board_id: usize, status: &mut PlayerStatus) {
+    let mut hit = false;
+    {
+        let board = &mut world.boards[board_id].0;
+        let player_pos = board.player_pos;
+        for &dir in &DIRECTIONS {
+            let pos = match adjust_coordinate(player_pos, board, dir) {
+                Some(pos) => pos,
+                None => continue,
+            };
+            let param = board.level_at(&pos).unwrap().2;
+            if board.thing_at(&pos).unwrap() != Thing::Bullet || is_player_bullet(param) {
+                continue;
+            }
+            if adjust_coordinate(pos, board, bullet_direction(param)) == Some(player_pos) {
+                board.remove_thing_at(&pos).unwrap();
+                hit = true;
+            }
+        }
+    }
+    if hit {
+        hurt_player(world, board_id, status, damage_from(Thing::Bullet).unwrap());
+    }
+}
+
+/// Every enemy two cells away from the player in a straight line, with the
+/// direction it would step in to end up next to them and what its cell holds.
+pub(crate) fn enemies_in_line(board: &Board) -> Vec<(Coordinate<u16>, (i16, i16), (u8, u8, u8))> {
+    [(0, -1), (0, 1), (1, 0), (-1, 0)]
+        .iter()
+        .filter_map(|&(xdiff, ydiff)| {
+            let pos = adjust_coordinate_diff(board.player_pos, board, xdiff * 2, ydiff * 2)?;
+            if is_enemy(board.thing_at(&pos).unwrap()) {
+                Some((pos, (-xdiff, -ydiff), *board.level_at(&pos).unwrap()))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// MegaZeux enemies attack by moving into the player, which the board update
+/// can't report. An enemy that stepped straight at the player in the previous
+/// update and stayed put in this one was stopped by the player, so it hurts them
+/// here. `in_line` is what `enemies_in_line` found before this update.
+pub(crate) fn hurt_by_enemies(
+    world: &mut World,
+    board_id: usize,
+    status: &mut PlayerStatus,
+    in_line: &[(Coordinate<u16>, (i16, i16), (u8, u8, u8))],
+) {
+    let mut damage = 0;
+    {
+        let board = &world.boards[board_id].0;
+        let player_pos = board.player_pos;
+        for &(pos, (xdiff, ydiff), level) in &status.approaching {
+            let facing_player = adjust_coordinate_diff(pos, board, xdiff, ydiff) == Some(player_pos);
+            if facing_player && *board.level_at(&pos).unwrap() == level {
+                damage = damage.max(damage_from(board.thing_at(&pos).unwrap()).unwrap_or(0));
+            }
+        }
+
+        status.approaching = in_line
+            .iter()
+            .filter_map(|&(from, (xdiff, ydiff), level)| {
+                let to = adjust_coordinate_diff(from, board, xdiff, ydiff)?;
+                let arrived = *board.level_at(&to).unwrap();
+                let moved = *board.level_at(&from).unwrap() != level;
+                if moved && (arrived.0, arrived.1) == (level.0, level.1) {
+                    Some((to, (xdiff, ydiff), arrived))
+                } else {
+                    None
+                }
+            })
+            .collect();
+    }
+    hurt_player(world, board_id, status, damage);
+}
+
+/// Move the player back to where they entered the current board, if that spot is free.
+fn restart_player(
+    board: &mut Board,
+    robots: &mut Vec<Robot>,
+    status: &PlayerStatus,
+    update_done: &mut [bool],
+) {
+    let entrance = match status.entrance {
+        Some((_, pos)) => pos,
+        None => return,
+    };
+    let player_pos = board.player_pos;
+    if entrance == player_pos || board.thing_at(&entrance).unwrap().is_solid() {
+        return;
+    }
+    if move_level_to(board, robots, &player_pos, &entrance, update_done).is_ok() {
+        board.player_pos = entrance;
+        reset_view(board);
+    }
+}
+
+/// Apply the effect of a potion or ring the player just picked up.
+pub(crate) fn apply_potion(
+    world: &mut World,
+    board_id: usize,
+    status: &mut PlayerStatus,
+    effect: u8,
+) {
+    let message = match effect {
+        0 => "* No effect *",
+        1 => {
+            status.effects.invincible = INVINCO_CYCLES;
+            "* Invinco *"
+        }
+        2 => {
+            let (ref mut board, _) = world.boards[board_id];
+            let player_pos = board.player_pos;
+            for &(xdiff, ydiff) in &[(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                if let Some(pos) = adjust_coordinate_diff(player_pos, board, xdiff, ydiff) {
+                    if !board.thing_at(&pos).unwrap().is_solid() {
+                        put_explosion(board, &pos, &mut *world.state.update_done);
+                    }
+                }
+            }
+            "* Blast *"
+        }
+        3 => {
+            heal(world, 10);
+            "* Healing 10 *"
+        }
+        4 => {
+            heal(world, 50);
+            "* Healing 50 *"
+        }
+        5 => {
+            hurt_player(world, board_id, status, 10);
+            "* Poison *"
+        }
+        6 => {
+            status.effects.blind = EFFECT_CYCLES;
+            "* Blind *"
+        }
+        7 => {
+            replace_things(&mut world.boards[board_id].0, is_enemy, |board, pos| {
+                board.remove_thing_at(pos).unwrap();
+            });
+            "* Kill enemies *"
+        }
+        8 => {
+            status.effects.firewalking = EFFECT_CYCLES;
+            "* Lava walk *"
+        }
+        9 => {
+            let update_done = &mut *world.state.update_done;
+            replace_things(
+                &mut world.boards[board_id].0,
+                |thing| thing == Thing::Bomb || thing == Thing::LitBomb,
+                |board, pos| put_explosion(board, pos, update_done),
+            );
+            "* Detonate *"
+        }
+        10 => {
+            replace_things(&mut world.boards[board_id].0, |thing| thing == Thing::Ghost, |board, pos| {
+                board.remove_thing_at(pos).unwrap();
+            });
+            "* Banish *"
+        }
+        11 => {
+            let (ref mut board, _) = world.boards[board_id];
+            let player_pos = board.player_pos;
+            for &(xdiff, ydiff) in &[(-2, 0), (2, 0), (0, -2), (0, 2)] {
+                if let Some(pos) = adjust_coordinate_diff(player_pos, board, xdiff, ydiff) {
+                    if board.thing_at(&pos).unwrap() == Thing::Space {
+                        put_at(board, &pos, 0x0F, Thing::Ghost, 0, &mut *world.state.update_done).unwrap();
+                    }
+                }
+            }
+            "* Summon *"
+        }
+        12 => {
+            let mut rng = rand::thread_rng();
+            let update_done = &mut *world.state.update_done;
+            replace_things(&mut world.boards[board_id].0, |thing| thing == Thing::Space, |board, pos| {
+                if rng.gen_range(0, 18) == 0 {
+                    put_at(board, pos, 0x07, Thing::Boulder, 0, update_done).unwrap();
+                }
+            });
+            "* Avalanche *"
+        }
+        13 => {
+            status.effects.freeze_time = EFFECT_CYCLES;
+            "* Freeze time *"
+        }
+        14 => {
+            status.effects.wind = EFFECT_CYCLES;
+            "* Wind *"
+        }
+        15 => {
+            status.effects.slow_time = EFFECT_CYCLES;
+            "* Slow time *"
+        }
+        _ => {
+            warn!("unknown potion effect {}", effect);
+            return;
+        }
+    };
+    world.boards[board_id].0.set_message_line(message.into());
+}
+
+/// Restore health, up to the world's limit.
+fn heal(world: &mut World, amount: u32) {
+    world.state.health = (world.state.health + amount).min(world.limit_health as u32);
+}
+
+fn put_explosion(board: &mut Board, pos: &Coordinate<u16>, update_done: &mut [bool]) {
+    put_at(board, pos, 0x0C, Thing::Explosion, POTION_EXPLOSION_SIZE, update_done).unwrap();
+}
+
+/// Call `replace` for every cell on the board holding a thing that matches `filter`.
+fn replace_things<F, R>(board: &mut Board, filter: F, mut replace: R)
+    where F: Fn(Thing) -> bool, R: FnMut(&mut Board, &Coordinate<u16>)
+{
+    for y in 0..board.height {
+        for x in 0..board.width {
+            let pos = Coordinate(x as u16, y as u16);
+            if filter(board.thing_at(&pos).unwrap()) {
+                replace(board, &pos);
+            }
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! Writing everything the mixer plays to a WAV file.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+const CHANNELS: u16 = 2;
+const BYTES_PER_SAMPLE: u16 = 4;
+/// The format tag for samples stored as 32-bit floats.
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const HEADER_LEN: u32 = 44;
+
+fn write_u16<W: Write>(w: &mut W, value: u16) -> io::Result<()> {
+    w.write_all(&[value as u8, (value >> 8) as u8])
+}
+
+fn write_u32<W: Write>(w: &mut W, value: u32) -> io::Result<()> {
+    w.write_all(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8])
+}
+
+fn write_header<W: Write>(w: &mut W, rate: u32, data_len: u32) -> io::Result<()> {
+    let block_align = CHANNELS * BYTES_PER_SAMPLE;
+    w.write_all(b"RIFF")?;
+    write_u32(w, HEADER_LEN - 8 + data_len)?;
+    w.write_all(b"WAVEfmt ")?;
+    write_u32(w, 16)?;
+    write_u16(w, WAVE_FORMAT_IEEE_FLOAT)?;
+    write_u16(w, CHANNELS)?;
+    write_u32(w, rate)?;
+    write_u32(w, rate * block_align as u32)?;
+    write_u16(w, block_align)?;
+    write_u16(w, BYTES_PER_SAMPLE * 8)?;
+    w.write_all(b"data")?;
+    write_u32(w, data_len)
+}
+
+fn record(
+    file: File,
+    rate: u32,
+    buffers: Receiver<Vec<f32>>,
+    spare: Sender<Vec<f32>>,
+) -> io::Result<()> {
+    let mut w = BufWriter::new(file);
+    // The sizes in the header aren't known until the recording ends.
+    write_header(&mut w, rate, 0)?;
+    let mut data_len: u32 = 0;
+    for buffer in buffers {
+        data_len = data_len.saturating_add(buffer.len() as u32 * BYTES_PER_SAMPLE as u32);
+        for sample in &buffer {
+            write_u32(&mut w, sample.to_bits())?;
+        }
+        // Hand the buffer back so the mixer doesn't have to allocate another.
+        let _ = spare.send(buffer);
+    }
+    w.seek(SeekFrom::Start(0))?;
+    write_header(&mut w, rate, data_len)?;
+    w.flush()
+}
+
+/// Writes the buffers the mixer sends to a WAV file on a thread of its own, so
+/// the mixer never waits on the disk. Written buffers go back through `spare`.
+pub(crate) struct AudioRecorder {
+    thread: JoinHandle<()>,
+}
+
+impl AudioRecorder {
+    pub fn start(
+        path: &Path,
+        rate: u32,
+        buffers: Receiver<Vec<f32>>,
+        spare: Sender<Vec<f32>>,
+    ) -> io::Result<AudioRecorder> {
+        let file = File::create(path)?;
+        let path = path.to_owned();
+        let thread = thread::spawn(move || {
+            if let Err(e) = record(file, rate, buffers, spare) {
+                warn!("Error recording audio to {} ({})", path.display(), e);
+            }
+        });
+        Ok(AudioRecorder { thread })
+    }
+
+    /// Wait for the rest of the recording to be written. Everything sending
+    /// buffers must have been dropped first.
+    pub fn finish(self) {
+        let _ = self.thread.join();
+    }
+}
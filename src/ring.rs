@@ -0,0 +1,112 @@
+//! A fixed-size queue of samples between one writer and one reader, neither of
+//! which ever blocks or allocates.
+
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+pub(crate) struct SampleRing {
+    /// The bits of each sample, since there are no atomic floats.
+    samples: Box<[AtomicU32]>,
+    /// How many samples have ever been pushed. Only the writer changes this.
+    pushed: AtomicUsize,
+    /// How many samples have ever been popped. Only the reader changes this.
+    popped: AtomicUsize,
+}
+
+impl SampleRing {
+    pub fn new(capacity: usize) -> SampleRing {
+        SampleRing {
+            samples: (0..capacity).map(|_| AtomicU32::new(0)).collect::<Vec<_>>().into_boxed_slice(),
+            pushed: AtomicUsize::new(0),
+            popped: AtomicUsize::new(0),
+        }
+    }
+
+    /// How many samples can be pushed without overwriting any that haven't been
+    /// popped yet.
+    pub fn free(&self) -> usize {
+        let pushed = self.pushed.load(Ordering::Relaxed);
+        let popped = self.popped.load(Ordering::Acquire);
+        self.samples.len() - pushed.wrapping_sub(popped)
+    }
+
+    /// Push as many of `samples` as fit, returning how many did.
+    pub fn push(&self, samples: &[f32]) -> usize {
+        let pushed = self.pushed.load(Ordering::Relaxed);
+        let count = samples.len().min(self.free());
+        for (i, sample) in samples[..count].iter().enumerate() {
+            let slot = pushed.wrapping_add(i) % self.samples.len();
+            self.samples[slot].store(sample.to_bits(), Ordering::Relaxed);
+        }
+        self.pushed.store(pushed.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    /// Fill the start of `out` with the oldest samples, returning how many there
+    /// were.
+    pub fn pop(&self, out: &mut [f32]) -> usize {
+        let popped = self.popped.load(Ordering::Relaxed);
+        let available = self.pushed.load(Ordering::Acquire).wrapping_sub(popped);
+        let count = out.len().min(available);
+        for (i, sample) in out[..count].iter_mut().enumerate() {
+            let slot = popped.wrapping_add(i) % self.samples.len();
+            *sample = f32::from_bits(self.samples[slot].load(Ordering::Relaxed));
+        }
+        self.popped.store(popped.wrapping_add(count), Ordering::Release);
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SampleRing;
+
+    #[test]
+    fn empty_ring_pops_nothing() {
+        let ring = SampleRing::new(4);
+        let mut out = [1.; 3];
+        assert_eq!(ring.pop(&mut out), 0);
+        assert_eq!(out, [1.; 3]);
+        assert_eq!(ring.free(), 4);
+    }
+
+    #[test]
+    fn full_ring_takes_no_more() {
+        let ring = SampleRing::new(4);
+        assert_eq!(ring.push(&[1., 2., 3., 4., 5.]), 4);
+        assert_eq!(ring.free(), 0);
+        assert_eq!(ring.push(&[6.]), 0);
+
+        let mut out = [0.; 5];
+        assert_eq!(ring.pop(&mut out), 4);
+        assert_eq!(out[..4], [1., 2., 3., 4.]);
+        assert_eq!(ring.free(), 4);
+    }
+
+    #[test]
+    fn push_and_pop_across_the_end() {
+        let ring = SampleRing::new(4);
+        let mut out = [0.; 4];
+        assert_eq!(ring.push(&[1., 2., 3.]), 3);
+        assert_eq!(ring.pop(&mut out[..2]), 2);
+        assert_eq!(out[..2], [1., 2.]);
+
+        // Starts at the last slot and wraps around to the first two.
+        assert_eq!(ring.push(&[4., 5., 6.]), 3);
+        assert_eq!(ring.free(), 0);
+        assert_eq!(ring.pop(&mut out), 4);
+        assert_eq!(out, [3., 4., 5., 6.]);
+    }
+
+    #[test]
+    fn counters_wrap_around() {
+        let ring = SampleRing::new(4);
+        ring.pushed.store(usize::max_value() - 1, super::Ordering::Relaxed);
+        ring.popped.store(usize::max_value() - 1, super::Ordering::Relaxed);
+        assert_eq!(ring.push(&[1., 2., 3.]), 3);
+        assert_eq!(ring.free(), 1);
+
+        let mut out = [0.; 3];
+        assert_eq!(ring.pop(&mut out), 3);
+        assert_eq!(out, [1., 2., 3.]);
+    }
+}
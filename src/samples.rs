@@ -0,0 +1,252 @@
+//! Digitized sound samples, in MegaZeux's raw .sam format or as .wav files.
+
+use crate::assets::AssetResolver;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+/// .sam files have no header, so they always play at this rate unless a
+/// frequency is requested.
+const SAM_RATE: u32 = 8363;
+/// How many samples can play at the same time.
+const NUM_CHANNELS: usize = 8;
+const SAMPLE_VOLUME: f32 = 0.5;
+
+pub(crate) struct Sample {
+    rate: u32,
+    data: Vec<f32>,
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+    bytes[0] as u16 | (bytes[1] as u16) << 8
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    read_u16(bytes) as u32 | (read_u16(&bytes[2..]) as u32) << 16
+}
+
+/// Signed 8-bit mono, with no header.
+fn decode_sam(bytes: &[u8]) -> Sample {
+    Sample {
+        rate: SAM_RATE,
+        data: bytes.iter().map(|&b| b as i8 as f32 / 128.).collect(),
+    }
+}
+
+/// Uncompressed 8 or 16-bit PCM, mixed down to mono.
+fn decode_wav(bytes: &[u8]) -> Result<Sample, String> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err("not a WAV file".to_owned());
+    }
+    let mut format = None;
+    let mut data = None;
+    let mut chunks = &bytes[12..];
+    while chunks.len() >= 8 {
+        let len = read_u32(&chunks[4..]) as usize;
+        let body = &chunks[8..];
+        let body = &body[..len.min(body.len())];
+        match &chunks[0..4] {
+            b"fmt " if body.len() >= 16 => format = Some(body),
+            b"data" => data = Some(body),
+            _ => (),
+        }
+        // Chunks are padded to an even length.
+        let next = 8 + len + (len & 1);
+        chunks = &chunks[next.min(chunks.len())..];
+    }
+    let (format, data) = match (format, data) {
+        (Some(format), Some(data)) => (format, data),
+        _ => return Err("missing fmt or data chunk".to_owned()),
+    };
+    if read_u16(format) != 1 {
+        return Err("only uncompressed WAV files are supported".to_owned());
+    }
+    let channels = read_u16(&format[2..]).max(1) as usize;
+    let rate = read_u32(&format[4..]);
+    let bits = read_u16(&format[14..]);
+    let samples: Vec<f32> = match bits {
+        8 => data.iter().map(|&b| (b as f32 - 128.) / 128.).collect(),
+        16 => data.chunks(2)
+            .filter(|c| c.len() == 2)
+            .map(|c| read_u16(c) as i16 as f32 / 32768.)
+            .collect(),
+        _ => return Err(format!("unsupported sample size {}", bits)),
+    };
+    Ok(Sample {
+        rate,
+        data: samples
+            .chunks(channels)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+            .collect(),
+    })
+}
+
+fn load_sample(path: &Path) -> Result<Sample, String> {
+    let mut bytes = vec![];
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .map_err(|e| format!("Error opening {} ({})", path.display(), e))?;
+    let is_wav = path.extension().map_or(false, |ext| ext.to_string_lossy().eq_ignore_ascii_case("wav"));
+    if is_wav {
+        decode_wav(&bytes).map_err(|e| format!("Error reading {} ({})", path.display(), e))
+    } else {
+        Ok(decode_sam(&bytes))
+    }
+}
+
+/// Every sample a world has played so far, so each file is only read once.
+#[derive(Default)]
+pub(crate) struct SampleCache {
+    samples: HashMap<String, Arc<Sample>>,
+}
+
+impl SampleCache {
+    pub fn get(&mut self, assets: &mut AssetResolver, file_path: &str) -> Option<Arc<Sample>> {
+        let file_path = file_path.to_ascii_lowercase();
+        if let Some(sample) = self.samples.get(&file_path) {
+            return Some(sample.clone());
+        }
+        match load_sample(&assets.resolve(&file_path)) {
+            Ok(sample) if !sample.data.is_empty() => {
+                let sample = Arc::new(sample);
+                self.samples.insert(file_path, sample.clone());
+                Some(sample)
+            }
+            Ok(_) => None,
+            Err(e) => {
+                eprintln!("{}", e);
+                None
+            }
+        }
+    }
+
+    /// Forget every cached sample, for when another world is loaded.
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+}
+
+struct Channel {
+    sample: Arc<Sample>,
+    position: f64,
+    step: f64,
+}
+
+/// Mixes samples on a fixed number of channels.
+#[derive(Default)]
+pub(crate) struct SamplePlayer {
+    channels: Vec<Channel>,
+}
+
+impl SamplePlayer {
+    /// Play `sample` at `frequency` samples per second, or at the file's own rate
+    /// if that is 0.
+    pub fn play(&mut self, sample: Arc<Sample>, frequency: u32, rate: i32) {
+        if rate <= 0 {
+            return;
+        }
+
+        let frequency = if frequency == 0 { sample.rate } else { frequency };
+        let channel = Channel {
+            sample,
+            position: 0.,
+            step: frequency as f64 / rate as f64,
+        };
+        if self.channels.len() < NUM_CHANNELS {
+            self.channels.push(channel);
+        } else {
+            // Cut off whichever sample is closest to finishing anyway.
+            let (oldest, _) = self.channels
+                .iter()
+                .enumerate()
+                .max_by(|a, b| {
+                    let progress = |c: &Channel| c.position / c.sample.data.len() as f64;
+                    progress(a.1).partial_cmp(&progress(b.1)).unwrap()
+                })
+                .unwrap();
+            self.channels[oldest] = channel;
+        }
+    }
+
+    /// Add every playing sample to interleaved stereo samples.
+    pub fn mix(&mut self, out: &mut [f32], volume: f32) {
+        for channel in &mut self.channels {
+            for frame in out.chunks_mut(2) {
+                let value = match channel.sample.data.get(channel.position as usize) {
+                    Some(&value) => value * SAMPLE_VOLUME * volume,
+                    None => break,
+                };
+                channel.position += channel.step;
+                for sample in frame {
+                    *sample += value;
+                }
+            }
+        }
+        self.channels.retain(|channel| (channel.position as usize) < channel.sample.data.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decode_wav;
+
+    fn wav(format: u16, channels: u16, rate: u32, bits: u16, data: &[u8]) -> Vec<u8> {
+        let mut bytes = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&format.to_le_bytes());
+        bytes.extend_from_slice(&channels.to_le_bytes());
+        bytes.extend_from_slice(&rate.to_le_bytes());
+        let block = channels as u32 * bits as u32 / 8;
+        bytes.extend_from_slice(&(rate * block).to_le_bytes());
+        bytes.extend_from_slice(&(block as u16).to_le_bytes());
+        bytes.extend_from_slice(&bits.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn mono_8_bit() {
+        let sample = decode_wav(&wav(1, 1, 11025, 8, &[128, 255, 0])).unwrap();
+        assert_eq!(sample.rate, 11025);
+        assert_eq!(sample.data, vec![0., 127. / 128., -1.]);
+    }
+
+    #[test]
+    fn stereo_16_bit_is_mixed_down() {
+        let mut data = vec![];
+        for &value in &[16384i16, 0, -32768, -32768] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        let sample = decode_wav(&wav(1, 2, 44100, 16, &data)).unwrap();
+        assert_eq!(sample.rate, 44100);
+        assert_eq!(sample.data, vec![0.25, -1.]);
+    }
+
+    #[test]
+    fn odd_length_chunks_are_skipped() {
+        let mut bytes = wav(1, 1, 8000, 8, &[128]);
+        // Move the data chunk after a padded three byte chunk.
+        let data = bytes.split_off(36);
+        bytes.extend_from_slice(b"LIST\x03\0\0\0abc\0");
+        bytes.extend_from_slice(&data);
+        let sample = decode_wav(&bytes).unwrap();
+        assert_eq!(sample.data, vec![0.]);
+    }
+
+    #[test]
+    fn rejected_files() {
+        assert!(decode_wav(b"").is_err());
+        assert!(decode_wav(b"RIFF\0\0\0\0AVI LIST").is_err());
+        // IEEE float.
+        assert!(decode_wav(&wav(3, 1, 8000, 32, &[0; 4])).is_err());
+        assert!(decode_wav(&wav(1, 1, 8000, 24, &[0; 3])).is_err());
+
+        let mut no_data = wav(1, 1, 8000, 8, &[128]);
+        no_data.truncate(36);
+        assert!(decode_wav(&no_data).is_err());
+    }
+}
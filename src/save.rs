@@ -0,0 +1,522 @@
+//! Saved games.
+//!
+//! libmzx can read worlds but has no way to write one back out, so saves can't use
+//! MegaZeux's own .sav layout, and they get an extension of their own so MegaZeux
+//! never tries to open them. Instead a save records everything about a session
+//! that differs from the world file it was started from: the contents and settings
+//! of every board, the state of every robot's program, the counters, the charset
+//! and palette, the player's state and the music that was playing. Loading reads
+//! the world file again and applies the save on top of it.
+
+use crate::audio::MusicCallback;
+use crate::player::Effects;
+use libmzx::{Board, World, Renderer, render};
+use libmzx::audio::AudioEngine;
+use libmzx::board::reset_view;
+use libmzx::{BoardId, ByteString, CardinalDirection, Coordinate, Counters, Robot, RunStatus};
+use num_traits::{FromPrimitive, ToPrimitive};
+use std::fs::File;
+use std::io::{self, Read, Write, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const SAVE_MAGIC: &[u8] = b"MZXPSAV4";
+const SAVE_EXTENSION: &str = "mzxpsav";
+
+/// Number of numbered quicksave slots.
+pub(crate) const NUM_SLOTS: u8 = 9;
+
+/// Robots ask for the game to be saved or loaded by setting one of these
+/// counters: 1 to 9 pick a quicksave slot, and any other value but 0 the main
+/// save file. MegaZeux's own SAVE_GAME and LOAD_GAME take a file name, which
+/// libmzx's numeric counters can't hold.
+pub(crate) const SAVE_GAME_COUNTER: &str = "save_game";
+pub(crate) const LOAD_GAME_COUNTER: &str = "load_game";
+
+/// Stored for a key slot that is empty.
+const NO_KEY: u8 = 0xFF;
+/// Stored for a missing board exit or robot direction.
+const NONE_BYTE: u8 = 0xFF;
+
+/// Longest board title, counter name or module name a save may hold.
+const MAX_NAME_LEN: usize = 1024;
+/// Deepest subroutine stack a saved robot may have.
+const MAX_STACK_DEPTH: usize = 256;
+
+/// Thumbnails keep one of every `THUMBNAIL_SCALE` pixels in each direction.
+const THUMBNAIL_SCALE: usize = 8;
+const THUMBNAIL_WIDTH: usize = 640 / THUMBNAIL_SCALE;
+const THUMBNAIL_HEIGHT: usize = (350 + THUMBNAIL_SCALE - 1) / THUMBNAIL_SCALE;
+
+/// The save file for a numbered quicksave slot, or the world's main save file.
+pub(crate) fn save_path(world_file: &Path, slot: Option<u8>) -> PathBuf {
+    match slot {
+        Some(slot) => world_file.with_extension(format!("{}.{}", slot, SAVE_EXTENSION)),
+        None => world_file.with_extension(SAVE_EXTENSION),
+    }
+}
+
+pub(crate) struct Thumbnail {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<(u8, u8, u8)>,
+}
+
+struct ThumbnailRenderer(Thumbnail);
+
+impl Renderer for ThumbnailRenderer {
+    fn put_pixel(
+        &mut self,
+        x: usize,
+        y: usize,
+        r: u8,
+        g: u8,
+        b: u8,
+    ) {
+        if x % THUMBNAIL_SCALE != 0 || y % THUMBNAIL_SCALE != 0 {
+            return;
+        }
+        let (x, y) = (x / THUMBNAIL_SCALE, y / THUMBNAIL_SCALE);
+        if x < self.0.width && y < self.0.height {
+            self.0.pixels[y * self.0.width + x] = (r, g, b);
+        }
+    }
+
+    fn clear(&mut self) {
+        for pixel in &mut self.0.pixels {
+            *pixel = (0, 0, 0);
+        }
+    }
+}
+
+fn render_thumbnail(world: &World, board_id: usize) -> Thumbnail {
+    let mut renderer = ThumbnailRenderer(Thumbnail {
+        width: THUMBNAIL_WIDTH,
+        height: THUMBNAIL_HEIGHT,
+        pixels: vec![(0, 0, 0); THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT],
+    });
+    let (ref board, ref robots) = world.boards[board_id];
+    render(
+        &world.state,
+        (
+            board.upper_left_viewport,
+            board.viewport_size,
+        ),
+        board.scroll_offset,
+        board,
+        robots,
+        &mut renderer,
+        false,
+    );
+    renderer.0
+}
+
+/// What the load browser shows about a save without loading it.
+pub(crate) struct SaveInfo {
+    pub board_title: String,
+    pub play_time: Duration,
+    pub thumbnail: Thumbnail,
+}
+
+fn read_info<R: Read>(r: &mut R) -> io::Result<SaveInfo> {
+    let mut magic = [0; 8];
+    r.read_exact(&mut magic)?;
+    if &magic[..] != SAVE_MAGIC {
+        return Err(invalid("not a saved game"));
+    }
+
+    let board_title = String::from_utf8_lossy(&read_bytes(r, MAX_NAME_LEN)?).into_owned();
+    let play_time = Duration::from_secs(read_u32(r)? as u64);
+    let width = read_u32(r)? as usize;
+    let height = read_u32(r)? as usize;
+    if width > THUMBNAIL_WIDTH || height > THUMBNAIL_HEIGHT {
+        return Err(invalid("bad thumbnail"));
+    }
+    let raw_pixels = read_bytes(r, width * height * 3)?;
+    if raw_pixels.len() != width * height * 3 {
+        return Err(invalid("bad thumbnail"));
+    }
+    let pixels = raw_pixels.chunks(3).map(|p| (p[0], p[1], p[2])).collect();
+    Ok(SaveInfo {
+        board_title,
+        play_time,
+        thumbnail: Thumbnail { width, height, pixels },
+    })
+}
+
+pub(crate) fn read_save_info(path: &Path) -> io::Result<SaveInfo> {
+    read_info(&mut BufReader::new(File::open(path)?))
+}
+
+fn write_u32<W: Write>(w: &mut W, value: u32) -> io::Result<()> {
+    w.write_all(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8])
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24)
+}
+
+fn write_bytes<W: Write>(w: &mut W, bytes: &[u8]) -> io::Result<()> {
+    write_u32(w, bytes.len() as u32)?;
+    w.write_all(bytes)
+}
+
+/// Read bytes written by `write_bytes`, failing if there are more than `max_len`
+/// of them rather than trusting the length in the file.
+fn read_bytes<R: Read>(r: &mut R, max_len: usize) -> io::Result<Vec<u8>> {
+    let len = read_u32(r)? as usize;
+    if len > max_len {
+        return Err(invalid("length out of range"));
+    }
+    let mut bytes = vec![0; len];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Clear a save or load request a robot made through `counter`, returning the
+/// slot it asked for.
+pub(crate) fn take_robot_request(counters: &mut Counters, counter: &str) -> Option<Option<u8>> {
+    let name = ByteString::from(counter);
+    let value = counters.get(&name).unwrap_or(0);
+    if value == 0 {
+        return None;
+    }
+    counters.set(name, 0);
+    Some(if value >= 1 && value <= NUM_SLOTS as i32 {
+        Some(value as u8)
+    } else {
+        None
+    })
+}
+
+fn dir_to_byte(dir: Option<CardinalDirection>) -> u8 {
+    match dir {
+        Some(CardinalDirection::North) => 0,
+        Some(CardinalDirection::South) => 1,
+        Some(CardinalDirection::East) => 2,
+        Some(CardinalDirection::West) => 3,
+        None => NONE_BYTE,
+    }
+}
+
+fn dir_from_byte(byte: u8) -> io::Result<Option<CardinalDirection>> {
+    Ok(match byte {
+        0 => Some(CardinalDirection::North),
+        1 => Some(CardinalDirection::South),
+        2 => Some(CardinalDirection::East),
+        3 => Some(CardinalDirection::West),
+        NONE_BYTE => None,
+        _ => return Err(invalid("bad direction")),
+    })
+}
+
+/// Robots keep their program; only the state of running it is saved.
+fn write_robot<W: Write>(w: &mut W, robot: &Robot) -> io::Result<()> {
+    write_u32(w, robot.position.0 as u32)?;
+    write_u32(w, robot.position.1 as u32)?;
+    write_u32(w, robot.current_line as u32)?;
+    write_u32(w, robot.current_loc as u32)?;
+    write_u32(w, robot.loop_count as u32)?;
+    w.write_all(&[
+        robot.ch,
+        robot.cycle,
+        robot.cycle_count,
+        robot.bullet_type,
+        robot.locked as u8,
+        robot.lavawalking as u8,
+        robot.alive as u8,
+        robot.status.to_u8().unwrap(),
+        dir_to_byte(robot.walk_dir),
+        dir_to_byte(robot.last_touched_dir),
+        dir_to_byte(robot.last_shot_dir),
+    ])?;
+    write_u32(w, robot.stack.len() as u32)?;
+    for &line in &robot.stack {
+        write_u32(w, line as u32)?;
+    }
+    write_u32(w, robot.local_counters.len() as u32)?;
+    for &value in robot.local_counters.iter() {
+        write_u32(w, value as u32)?;
+    }
+    Ok(())
+}
+
+/// Check a program line read from a save against the robot's program.
+fn read_line<R: Read>(r: &mut R, robot: &Robot) -> io::Result<usize> {
+    let line = read_u32(r)? as usize;
+    if line != 0 && line >= robot.program.len() {
+        return Err(invalid("robot line out of range"));
+    }
+    Ok(line)
+}
+
+fn read_robot<R: Read>(r: &mut R, robot: &mut Robot) -> io::Result<()> {
+    robot.position = Coordinate(read_u32(r)? as u16, read_u32(r)? as u16);
+    robot.current_line = read_line(r, robot)? as _;
+    robot.current_loc = read_u32(r)? as _;
+    robot.loop_count = read_u32(r)? as _;
+    let mut flags = [0; 11];
+    r.read_exact(&mut flags)?;
+    robot.ch = flags[0];
+    robot.cycle = flags[1];
+    robot.cycle_count = flags[2];
+    robot.bullet_type = flags[3];
+    robot.locked = flags[4] != 0;
+    robot.lavawalking = flags[5] != 0;
+    robot.alive = flags[6] != 0;
+    robot.status = RunStatus::from_u8(flags[7]).ok_or_else(|| invalid("bad robot status"))?;
+    robot.walk_dir = dir_from_byte(flags[8])?;
+    robot.last_touched_dir = dir_from_byte(flags[9])?;
+    robot.last_shot_dir = dir_from_byte(flags[10])?;
+
+    let depth = read_u32(r)? as usize;
+    if depth > MAX_STACK_DEPTH {
+        return Err(invalid("robot stack too deep"));
+    }
+    let mut stack = vec![];
+    for _ in 0..depth {
+        stack.push(read_line(r, robot)? as _);
+    }
+    robot.stack = stack;
+
+    let num_locals = read_u32(r)? as usize;
+    if num_locals != robot.local_counters.len() {
+        return Err(invalid("bad robot"));
+    }
+    for local in robot.local_counters.iter_mut() {
+        *local = read_u32(r)? as i32;
+    }
+    Ok(())
+}
+
+/// The settings robots can change on a board, beyond what is in its cells.
+fn write_board_settings<W: Write>(w: &mut W, board: &Board) -> io::Result<()> {
+    for &value in &[
+        board.upper_left_viewport.0,
+        board.upper_left_viewport.1,
+        board.viewport_size.0,
+        board.viewport_size.1,
+    ] {
+        write_u32(w, value as u32)?;
+    }
+    let (north, south, east, west) = board.exits;
+    let exits: Vec<u8> = [north, south, east, west]
+        .iter()
+        .map(|exit| exit.map_or(NONE_BYTE, |id| id.0))
+        .collect();
+    w.write_all(&exits)?;
+    w.write_all(&[
+        board.restart_if_zapped as u8,
+        board.player_locked_ns as u8,
+        board.player_locked_ew as u8,
+        board.player_locked_attack as u8,
+    ])
+}
+
+fn read_board_settings<R: Read>(r: &mut R, board: &mut Board) -> io::Result<()> {
+    board.upper_left_viewport = (read_u32(r)? as _, read_u32(r)? as _);
+    board.viewport_size = (read_u32(r)? as _, read_u32(r)? as _);
+    let mut exits = [0; 4];
+    r.read_exact(&mut exits)?;
+    let exit = |id: u8| if id == NONE_BYTE { None } else { Some(BoardId(id)) };
+    board.exits = (exit(exits[0]), exit(exits[1]), exit(exits[2]), exit(exits[3]));
+    let mut flags = [0; 4];
+    r.read_exact(&mut flags)?;
+    board.restart_if_zapped = flags[0] != 0;
+    board.player_locked_ns = flags[1] != 0;
+    board.player_locked_ew = flags[2] != 0;
+    board.player_locked_attack = flags[3] != 0;
+    Ok(())
+}
+
+pub(crate) fn save_game(
+    path: &Path,
+    world: &World,
+    board_id: usize,
+    counters: &Counters,
+    effects: &Effects,
+    music: &MusicCallback,
+    play_time: Duration,
+) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    w.write_all(SAVE_MAGIC)?;
+    write_bytes(&mut w, &world.boards[board_id].0.title)?;
+    write_u32(&mut w, play_time.as_secs() as u32)?;
+    let thumbnail = render_thumbnail(world, board_id);
+    write_u32(&mut w, thumbnail.width as u32)?;
+    write_u32(&mut w, thumbnail.height as u32)?;
+    let raw_pixels: Vec<u8> = thumbnail.pixels.iter().flat_map(|&(r, g, b)| vec![r, g, b]).collect();
+    write_bytes(&mut w, &raw_pixels)?;
+
+    write_u32(&mut w, board_id as u32)?;
+
+    let state = &world.state;
+    for &value in &[state.health, state.lives, state.ammo, state.gems, state.coins, state.score] {
+        write_u32(&mut w, value)?;
+    }
+    let keys: Vec<u8> = state.keys.iter().map(|key| key.unwrap_or(NO_KEY)).collect();
+    write_bytes(&mut w, &keys)?;
+    w.write_all(&[state.scroll_locked as u8, state.player_face_dir as u8])?;
+    write_bytes(&mut w, &state.charset.data[..])?;
+    let palette: Vec<u8> = state.palette.colors.iter().flat_map(|&(r, g, b)| vec![r, g, b]).collect();
+    write_bytes(&mut w, &palette)?;
+    for &timer in &effects.timers() {
+        write_u32(&mut w, timer as u32)?;
+    }
+
+    let counters: Vec<_> = counters.iter().collect();
+    write_u32(&mut w, counters.len() as u32)?;
+    for (name, &value) in counters {
+        write_bytes(&mut w, name)?;
+        write_u32(&mut w, value as u32)?;
+    }
+
+    let (module, order) = music.current_module().unwrap_or((String::new(), 0));
+    write_bytes(&mut w, module.as_bytes())?;
+    write_u32(&mut w, order as u32)?;
+
+    write_u32(&mut w, world.boards.len() as u32)?;
+    for &(ref board, ref robots) in &world.boards {
+        write_u32(&mut w, board.width as u32)?;
+        write_u32(&mut w, board.height as u32)?;
+        write_u32(&mut w, board.player_pos.0 as u32)?;
+        write_u32(&mut w, board.player_pos.1 as u32)?;
+        write_board_settings(&mut w, board)?;
+        for y in 0..board.height {
+            for x in 0..board.width {
+                let pos = Coordinate(x as u16, y as u16);
+                let &(id, color, param) = board.level_at(&pos).unwrap();
+                let &(under_id, under_color, under_param) = board.under_at(&pos).unwrap();
+                w.write_all(&[id, color, param, under_id, under_color, under_param])?;
+            }
+        }
+        write_u32(&mut w, robots.len() as u32)?;
+        for robot in robots {
+            write_robot(&mut w, robot)?;
+        }
+    }
+    write_robot(&mut w, &world.global_robot)?;
+    w.flush()
+}
+
+/// Apply the save at `path` to `world`, which must be freshly loaded from the
+/// world file the save was made with. `counters` and `effects` are only replaced
+/// once the whole save has been read. Returns the play time recorded in the save.
+pub(crate) fn load_game(
+    path: &Path,
+    world: &mut World,
+    board_id: &mut usize,
+    counters: &mut Counters,
+    effects: &mut Effects,
+    music: &MusicCallback,
+) -> io::Result<Duration> {
+    let mut r = BufReader::new(File::open(path)?);
+    let info = read_info(&mut r)?;
+
+    let saved_board_id = read_u32(&mut r)? as usize;
+    let mut values = [0; 6];
+    for value in values.iter_mut() {
+        *value = read_u32(&mut r)?;
+    }
+    let keys = read_bytes(&mut r, world.state.keys.len())?;
+    if keys.len() != world.state.keys.len() {
+        return Err(invalid("bad keys"));
+    }
+    let mut flags = [0; 2];
+    r.read_exact(&mut flags)?;
+    let charset = read_bytes(&mut r, world.state.charset.data.len())?;
+    if charset.len() != world.state.charset.data.len() {
+        return Err(invalid("bad charset"));
+    }
+    let palette = read_bytes(&mut r, world.state.palette.colors.len() * 3)?;
+    if palette.len() != world.state.palette.colors.len() * 3 {
+        return Err(invalid("bad palette"));
+    }
+    let mut timers = [0; 6];
+    for timer in timers.iter_mut() {
+        *timer = read_u32(&mut r)? as u16;
+    }
+
+    let num_counters = read_u32(&mut r)? as usize;
+    let mut saved_counters = vec![];
+    for _ in 0..num_counters {
+        let name = ByteString::from(read_bytes(&mut r, MAX_NAME_LEN)?);
+        saved_counters.push((name, read_u32(&mut r)? as i32));
+    }
+    let module = String::from_utf8(read_bytes(&mut r, MAX_NAME_LEN)?).map_err(|_| invalid("bad module name"))?;
+    let order = read_u32(&mut r)? as i32;
+
+    let num_boards = read_u32(&mut r)? as usize;
+    if num_boards != world.boards.len() || saved_board_id >= num_boards {
+        return Err(invalid("saved game is for a different world"));
+    }
+    for &mut (ref mut board, ref mut robots) in &mut world.boards {
+        let width = read_u32(&mut r)? as usize;
+        let height = read_u32(&mut r)? as usize;
+        if width != board.width || height != board.height {
+            return Err(invalid("saved game is for a different world"));
+        }
+        let player_pos = Coordinate(read_u32(&mut r)? as u16, read_u32(&mut r)? as u16);
+        read_board_settings(&mut r, board)?;
+        for y in 0..height {
+            for x in 0..width {
+                let pos = Coordinate(x as u16, y as u16);
+                let mut cell = [0; 6];
+                r.read_exact(&mut cell)?;
+                *board.level_at_mut(&pos).unwrap() = (cell[0], cell[1], cell[2]);
+                *board.under_at_mut(&pos).unwrap() = (cell[3], cell[4], cell[5]);
+            }
+        }
+        board.player_pos = player_pos;
+        reset_view(board);
+
+        // Robots can't be recreated from a save, only brought up to date.
+        if read_u32(&mut r)? as usize != robots.len() {
+            return Err(invalid("saved game is for a different world"));
+        }
+        for robot in robots.iter_mut() {
+            read_robot(&mut r, robot)?;
+        }
+    }
+    read_robot(&mut r, &mut world.global_robot)?;
+
+    let state = &mut world.state;
+    state.health = values[0];
+    state.lives = values[1];
+    state.ammo = values[2];
+    state.gems = values[3];
+    state.coins = values[4];
+    state.score = values[5];
+    for (key, &saved) in state.keys.iter_mut().zip(&keys) {
+        *key = if saved == NO_KEY { None } else { Some(saved) };
+    }
+    state.scroll_locked = flags[0] != 0;
+    state.player_face_dir = flags[1] as _;
+    state.charset.data.copy_from_slice(&charset);
+    for (color, rgb) in state.palette.colors.iter_mut().zip(palette.chunks(3)) {
+        *color = (rgb[0], rgb[1], rgb[2]);
+    }
+    *board_id = saved_board_id;
+
+    for (timer, &saved) in effects.timers_mut().iter_mut().zip(&timers) {
+        **timer = saved;
+    }
+    *counters = Counters::new();
+    for (name, value) in saved_counters {
+        counters.set(name, value);
+    }
+
+    if module.is_empty() {
+        music.end_module();
+    } else {
+        music.load_module(&module);
+        music.set_mod_order(order);
+    }
+    Ok(info.play_time)
+}
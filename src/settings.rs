@@ -0,0 +1,276 @@
+use crate::{GameState, PoppedData, StateChange, SdlRenderer};
+use crate::audio::DEFAULT_FADE_CYCLES;
+use crate::game::InputState;
+use crate::menu::navigate_list;
+use libmzx::{World, Counters, ByteString, MessageBoxLine, MessageBoxLineType, draw_messagebox};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use std::cell::{Ref, RefCell};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::rc::Rc;
+
+const MIN_GAME_SPEED: u64 = 1;
+const MAX_GAME_SPEED: u64 = 9;
+const MAX_FADE_CYCLES: u32 = 128;
+const FADE_CYCLES_STEP: u32 = 8;
+/// Volumes go from 0 to 10, as in MegaZeux's config file.
+pub(crate) const MAX_VOLUME: u8 = 10;
+
+/// The counters robots use to read and change the volumes of each kind of output.
+const MUSIC_VOLUME_COUNTER: &str = "music_volume";
+const SAMPLE_VOLUME_COUNTER: &str = "sound_volume";
+const SPEAKER_VOLUME_COUNTER: &str = "pcs_volume";
+
+/// Read from the working directory at startup, one `name = value` per line.
+pub(crate) const CONFIG_FILE: &str = "mzxplay.cfg";
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct Volumes {
+    pub master: u8,
+    pub music: u8,
+    pub samples: u8,
+    pub speaker: u8,
+}
+
+impl Volumes {
+    /// Each kind of output as a fraction of full volume, with the master volume
+    /// applied: (music, samples, speaker).
+    pub fn scaled(&self) -> (f32, f32, f32) {
+        let max = MAX_VOLUME as f32 * MAX_VOLUME as f32;
+        let master = self.master as f32;
+        (
+            self.music as f32 * master / max,
+            self.samples as f32 * master / max,
+            self.speaker as f32 * master / max,
+        )
+    }
+}
+
+pub(crate) struct SettingsData {
+    /// Higher is slower, as in MegaZeux.
+    pub game_speed: u64,
+    /// How many game cycles music takes to fade in or out.
+    pub fade_cycles: u32,
+    pub volumes: Volumes,
+    /// Allows starting the game at any board from the title screen.
+    pub developer_mode: bool,
+}
+
+impl Default for SettingsData {
+    fn default() -> SettingsData {
+        SettingsData {
+            game_speed: 4,
+            fade_cycles: DEFAULT_FADE_CYCLES,
+            volumes: Volumes {
+                master: MAX_VOLUME,
+                music: MAX_VOLUME,
+                samples: MAX_VOLUME,
+                speaker: MAX_VOLUME,
+            },
+            developer_mode: false,
+        }
+    }
+}
+
+impl SettingsData {
+    /// Change the setting called `name`, as spelled in the config file.
+    pub fn apply(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let number = |max: u64| -> Result<u64, String> {
+            match value.trim().parse::<u64>() {
+                Ok(n) if n <= max => Ok(n),
+                _ => Err(format!("{} must be a number from 0 to {}, not {:?}", name, max, value)),
+            }
+        };
+        match name.trim() {
+            "game_speed" => {
+                self.game_speed = number(MAX_GAME_SPEED)?.max(MIN_GAME_SPEED);
+            }
+            "music_fade" => self.fade_cycles = number(MAX_FADE_CYCLES as u64)? as u32,
+            "master_volume" => self.volumes.master = number(MAX_VOLUME as u64)? as u8,
+            "music_volume" => self.volumes.music = number(MAX_VOLUME as u64)? as u8,
+            "sample_volume" => self.volumes.samples = number(MAX_VOLUME as u64)? as u8,
+            "pc_speaker_volume" => self.volumes.speaker = number(MAX_VOLUME as u64)? as u8,
+            _ => return Err(format!("unknown setting {:?}", name)),
+        }
+        Ok(())
+    }
+
+    /// Apply every setting in the config file at `path`, if there is one.
+    pub fn load_config(&mut self, path: &Path) {
+        let mut contents = String::new();
+        match File::open(path) {
+            Ok(mut file) => if let Err(e) = file.read_to_string(&mut contents) {
+                warn!("Error reading {} ({})", path.display(), e);
+                return;
+            },
+            Err(_) => return,
+        }
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let result = match line.find('=') {
+                Some(split) => self.apply(&line[..split], &line[split + 1..]),
+                None => Err("expected name = value".to_owned()),
+            };
+            if let Err(e) = result {
+                warn!("{}:{}: {}", path.display(), i + 1, e);
+            }
+        }
+    }
+}
+
+/// Player preferences, shared between the main loop and every state that can
+/// show or change them.
+#[derive(Clone)]
+pub(crate) struct Settings(Rc<RefCell<SettingsData>>);
+
+impl Settings {
+    pub fn new(data: SettingsData) -> Settings {
+        Settings(Rc::new(RefCell::new(data)))
+    }
+
+    pub fn get(&self) -> Ref<SettingsData> {
+        self.0.borrow()
+    }
+
+    /// Expose the volumes to robots through their counters, after adopting any
+    /// values robots wrote since the volumes in `written` were last exposed.
+    pub fn sync_volume_counters(&self, counters: &mut Counters, written: &mut Option<Volumes>) {
+        let mut volumes = self.get().volumes;
+        if let Some(ref written) = *written {
+            let fields = [
+                (MUSIC_VOLUME_COUNTER, written.music, &mut volumes.music),
+                (SAMPLE_VOLUME_COUNTER, written.samples, &mut volumes.samples),
+                (SPEAKER_VOLUME_COUNTER, written.speaker, &mut volumes.speaker),
+            ];
+            for &mut (name, old, ref mut volume) in &mut fields {
+                let value = counters.get(&ByteString::from(name)).unwrap_or(old as i32);
+                if value != old as i32 {
+                    **volume = value.max(0).min(MAX_VOLUME as i32) as u8;
+                }
+            }
+        }
+        self.0.borrow_mut().volumes = volumes;
+
+        counters.set(ByteString::from(MUSIC_VOLUME_COUNTER), volumes.music as i32);
+        counters.set(ByteString::from(SAMPLE_VOLUME_COUNTER), volumes.samples as i32);
+        counters.set(ByteString::from(SPEAKER_VOLUME_COUNTER), volumes.speaker as i32);
+        *written = Some(volumes);
+    }
+
+    /// How long one game cycle lasts at the current game speed.
+    pub fn cycle_ms(&self) -> u64 {
+        16 * (self.get().game_speed - 1)
+    }
+}
+
+fn adjust_volume(volume: &mut u8, delta: i64) {
+    *volume = (*volume as i64 + delta).max(0).min(MAX_VOLUME as i64) as u8;
+}
+
+/// Adjusts the shared settings in place; changes apply immediately.
+pub(crate) struct SettingsState {
+    settings: Settings,
+    pos: usize,
+}
+
+impl SettingsState {
+    pub fn new(settings: Settings) -> SettingsState {
+        SettingsState {
+            settings,
+            pos: 0,
+        }
+    }
+
+    fn lines(&self) -> Vec<MessageBoxLine> {
+        let data = self.settings.get();
+        vec![
+            ("Game speed", data.game_speed as u32),
+            ("Music fade", data.fade_cycles),
+            ("Master volume", data.volumes.master as u32),
+            ("Music volume", data.volumes.music as u32),
+            ("Sample volume", data.volumes.samples as u32),
+            ("Speaker volume", data.volumes.speaker as u32),
+        ].into_iter()
+            .map(|(label, value)| format!("{:<16}< {} >", label, value))
+            .map(|text| MessageBoxLine::Text(text.into_bytes().into(), MessageBoxLineType::Plain))
+            .collect()
+    }
+
+    fn adjust(&mut self, delta: i64) {
+        let mut data = self.settings.0.borrow_mut();
+        match self.pos {
+            0 => {
+                let speed = (data.game_speed as i64 + delta).max(MIN_GAME_SPEED as i64) as u64;
+                data.game_speed = speed.min(MAX_GAME_SPEED);
+            }
+            1 => {
+                let cycles = (data.fade_cycles as i64 + delta * FADE_CYCLES_STEP as i64).max(0) as u32;
+                data.fade_cycles = cycles.min(MAX_FADE_CYCLES);
+            }
+            2 => adjust_volume(&mut data.volumes.master, delta),
+            3 => adjust_volume(&mut data.volumes.music, delta),
+            4 => adjust_volume(&mut data.volumes.samples, delta),
+            5 => adjust_volume(&mut data.volumes.speaker, delta),
+            _ => (),
+        }
+    }
+}
+
+impl GameState for SettingsState {
+    fn init(&mut self, _world: &mut World, _board_id: &mut usize) {
+    }
+
+    fn popped(&mut self, _world: &mut World, _board_id: usize, _data: PoppedData) {
+    }
+
+    fn input(
+        &mut self,
+        event: Event,
+        _input_state: &mut InputState,
+    ) -> Option<StateChange> {
+        if navigate_list(&event, &mut self.pos, self.lines().len()) {
+            return None;
+        }
+        match event {
+            Event::KeyDown {keycode: Some(Keycode::Escape), ..} |
+            Event::KeyDown {keycode: Some(Keycode::Return), ..} =>
+                return Some(StateChange::PopCurrent(None)),
+
+            Event::KeyDown {keycode: Some(Keycode::Left), ..} => self.adjust(-1),
+
+            Event::KeyDown {keycode: Some(Keycode::Right), ..} => self.adjust(1),
+
+            _ => (),
+        }
+
+        None
+    }
+
+    fn tick(
+        &mut self,
+        _world: &mut World,
+        _input_state: &InputState,
+        _counters: &mut Counters,
+        _boards: &[ByteString],
+        _board_id: &mut usize,
+    ) -> Option<StateChange> {
+        None
+    }
+
+    fn render(
+        &mut self,
+        world: &World,
+        _board_id: usize,
+        canvas: &mut Canvas<Window>,
+    ) {
+        let mut renderer = SdlRenderer { canvas };
+        draw_messagebox(&world.state, &"Settings".into(), &self.lines(), self.pos, &mut renderer);
+    }
+}
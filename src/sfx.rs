@@ -0,0 +1,237 @@
+//! PC speaker sound effects.
+//!
+//! Effects are written in MegaZeux's PLAY syntax:
+//!
+//! * `a`-`g` play a note, `x` is a rest
+//! * `#` and `!` make the following note sharp or flat
+//! * `+`/`>` and `-`/`<` move up or down an octave, `0`-`6` pick one directly
+//! * `z`, `t`, `s`, `i`, `q`, `h` and `w` set the length of the following notes,
+//!   from a 64th to a whole note, and `.` makes the next note dotted
+//!
+//! Notes are rendered as a square wave, like the speaker they imitate.
+
+use std::collections::VecDeque;
+
+/// Length of a 64th note, the shortest one the syntax can express.
+const UNIT_MS: u32 = 9;
+const DEFAULT_OCTAVE: i32 = 3;
+const DEFAULT_UNITS: u32 = 4;
+/// Middle C, the first note of the default octave.
+const BASE_FREQUENCY: f32 = 261.63;
+const VOLUME: f32 = 0.15;
+/// Effects that would make the queue longer than this are dropped.
+const MAX_QUEUED: usize = 256;
+
+/// Robot PLAY commands, which queue up behind whatever is already playing.
+#[cfg(feature = "robot-audio")]
+pub(crate) const ROBOT_PRIORITY: u8 = 0;
+/// Effects played by the game itself rather than by robots, which they interrupt.
+pub(crate) const BUILT_IN_PRIORITY: u8 = 1;
+pub(crate) const SFX_SHOOT: &[u8] = b"z5c4c";
+pub(crate) const SFX_OUT_OF_AMMO: &[u8] = b"z1cxc";
+pub(crate) const SFX_UNLOCK: &[u8] = b"z4cgecge";
+pub(crate) const SFX_LOCKED: &[u8] = b"z1c#c";
+pub(crate) const SFX_OPEN_DOOR: &[u8] = b"z2cfc";
+pub(crate) const SFX_TRANSPORT: &[u8] = b"z3cdefgab4cdefgab";
+
+#[derive(Clone, Copy)]
+pub(crate) struct Tone {
+    /// None for a rest.
+    frequency: Option<f32>,
+    duration_ms: u32,
+}
+
+fn semitone(note: u8) -> i32 {
+    match note {
+        b'c' => 0,
+        b'd' => 2,
+        b'e' => 4,
+        b'f' => 5,
+        b'g' => 7,
+        b'a' => 9,
+        _ => 11,
+    }
+}
+
+fn note_units(length: u8) -> u32 {
+    match length {
+        b'z' => 1,
+        b't' => 2,
+        b's' => 4,
+        b'i' => 8,
+        b'q' => 16,
+        b'h' => 32,
+        _ => 64,
+    }
+}
+
+pub(crate) fn parse_play(notes: &[u8]) -> Vec<Tone> {
+    let mut tones = vec![];
+    let mut octave = DEFAULT_OCTAVE;
+    let mut units = DEFAULT_UNITS;
+    let mut accidental = 0;
+    let mut dotted = false;
+    for &c in notes {
+        match c.to_ascii_lowercase() {
+            c @ b'a'..=b'g' | c @ b'x' => {
+                let frequency = if c == b'x' {
+                    None
+                } else {
+                    let steps = (octave - DEFAULT_OCTAVE) * 12 + semitone(c) + accidental;
+                    Some(BASE_FREQUENCY * 2f32.powf(steps as f32 / 12.))
+                };
+                let duration_units = if dotted { units * 3 / 2 } else { units };
+                tones.push(Tone {
+                    frequency,
+                    duration_ms: duration_units * UNIT_MS,
+                });
+                accidental = 0;
+                dotted = false;
+            }
+            b'#' => accidental = 1,
+            b'!' => accidental = -1,
+            b'+' | b'>' => octave = (octave + 1).min(6),
+            b'-' | b'<' => octave = (octave - 1).max(0),
+            c @ b'0'..=b'6' => octave = (c - b'0') as i32,
+            c @ b'z' | c @ b't' | c @ b's' | c @ b'i' | c @ b'q' | c @ b'h' | c @ b'w' =>
+                units = note_units(c),
+            b'.' => dotted = true,
+            _ => (),
+        }
+    }
+    tones
+}
+
+/// Plays queued tones one after another. A new effect with a higher priority than
+/// the one that is playing cuts it off; anything else waits its turn.
+#[derive(Default)]
+pub(crate) struct Speaker {
+    queue: VecDeque<Tone>,
+    priority: u8,
+    current: Option<Tone>,
+    remaining_samples: u64,
+    phase: f32,
+}
+
+impl Speaker {
+    pub fn play(&mut self, tones: Vec<Tone>, priority: u8) {
+        if self.is_playing() && priority > self.priority {
+            self.queue.clear();
+            self.current = None;
+            self.remaining_samples = 0;
+        }
+        if self.queue.len() + tones.len() > MAX_QUEUED {
+            return;
+        }
+        if !self.is_playing() {
+            self.priority = priority;
+        }
+        self.queue.extend(tones);
+    }
+
+    pub fn stop(&mut self) {
+        self.queue.clear();
+        self.current = None;
+        self.remaining_samples = 0;
+    }
+
+    fn is_playing(&self) -> bool {
+        self.current.is_some() || !self.queue.is_empty()
+    }
+
+    /// Add the speaker's output to interleaved stereo samples.
+    pub fn mix(&mut self, rate: i32, out: &mut [f32], volume: f32) {
+        for frame in out.chunks_mut(2) {
+            while self.remaining_samples == 0 {
+                self.current = self.queue.pop_front();
+                match self.current {
+                    Some(tone) => {
+                        self.remaining_samples = tone.duration_ms as u64 * rate as u64 / 1000;
+                        self.phase = 0.;
+                    }
+                    None => {
+                        self.priority = 0;
+                        return;
+                    }
+                }
+            }
+            self.remaining_samples -= 1;
+
+            let frequency = match self.current.and_then(|tone| tone.frequency) {
+                Some(frequency) => frequency,
+                None => continue,
+            };
+            let level = VOLUME * volume;
+            let value = if self.phase < 0.5 { level } else { -level };
+            self.phase = (self.phase + frequency / rate as f32) % 1.;
+            for sample in frame {
+                *sample += value;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frequencies(notes: &[u8]) -> Vec<Option<f32>> {
+        parse_play(notes).iter().map(|tone| tone.frequency).collect()
+    }
+
+    fn durations(notes: &[u8]) -> Vec<u32> {
+        parse_play(notes).iter().map(|tone| tone.duration_ms).collect()
+    }
+
+    fn assert_close(frequency: Option<f32>, expected: f32) {
+        let frequency = frequency.expect("expected a note, not a rest");
+        assert!((frequency - expected).abs() < 0.01, "{} != {}", frequency, expected);
+    }
+
+    #[test]
+    fn defaults() {
+        let tones = parse_play(b"c");
+        assert_eq!(tones.len(), 1);
+        assert_close(tones[0].frequency, BASE_FREQUENCY);
+        assert_eq!(tones[0].duration_ms, DEFAULT_UNITS * UNIT_MS);
+    }
+
+    #[test]
+    fn rests_and_unknown_characters() {
+        assert_eq!(frequencies(b" x ?"), vec![None]);
+        assert!(parse_play(b"").is_empty());
+    }
+
+    #[test]
+    fn octaves() {
+        let tones = frequencies(SFX_SHOOT);
+        assert_eq!(tones.len(), 2);
+        assert_close(tones[0], BASE_FREQUENCY * 4.);
+        assert_close(tones[1], BASE_FREQUENCY * 2.);
+
+        let tones = frequencies(b"+c<<c");
+        assert_close(tones[0], BASE_FREQUENCY * 2.);
+        assert_close(tones[1], BASE_FREQUENCY / 2.);
+
+        // Octaves stop at 0 and 6.
+        let tones = frequencies(b"0-c6>c");
+        assert_close(tones[0], BASE_FREQUENCY / 8.);
+        assert_close(tones[1], BASE_FREQUENCY * 8.);
+    }
+
+    #[test]
+    fn accidentals_only_apply_to_the_next_note() {
+        let tones = frequencies(b"#c!dc");
+        assert_close(tones[0], tones[1].unwrap());
+        assert_close(tones[0], BASE_FREQUENCY * 2f32.powf(1. / 12.));
+        assert_close(tones[2], BASE_FREQUENCY);
+    }
+
+    #[test]
+    fn lengths() {
+        assert_eq!(durations(b"zctcscicqchcwc"), vec![9, 18, 36, 72, 144, 288, 576]);
+        // Dots only apply to the next note, but lengths stick.
+        assert_eq!(durations(b"q.cc"), vec![216, 144]);
+        assert_eq!(durations(b"Z1CXC"), vec![9, 9, 9]);
+    }
+}